@@ -15,6 +15,7 @@ $do$;
 CREATE TABLE IF NOT EXISTS users
 (
     id       SERIAL                NOT NULL,
+    tenant   VARCHAR(64) DEFAULT 'default' NOT NULL,
     email    VARCHAR(320)          NOT NULL,
     username VARCHAR(32)           NOT NULL,
     password VARCHAR(128)          NOT NULL,
@@ -22,27 +23,86 @@ CREATE TABLE IF NOT EXISTS users
     role     role    DEFAULT 'user'::role
 );
 
-CREATE UNIQUE INDEX IF NOT EXISTS users_email_uindex
-    ON users (email);
+-- Databases created before tenants existed
+ALTER TABLE users ADD COLUMN IF NOT EXISTS tenant VARCHAR(64) DEFAULT 'default' NOT NULL;
+
+-- Auth tokens carry this version, bumping it logs out every session
+ALTER TABLE users ADD COLUMN IF NOT EXISTS token_version INTEGER DEFAULT 0 NOT NULL;
+
+-- In UTC, for password rotation policies
+ALTER TABLE users ADD COLUMN IF NOT EXISTS password_changed_at TIMESTAMP DEFAULT (now() AT TIME ZONE 'utc') NOT NULL;
+
+-- New email waiting for confirmation, the token is stored hashed
+ALTER TABLE users ADD COLUMN IF NOT EXISTS pending_email VARCHAR(320);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_token VARCHAR(64);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_token_expires_at TIMESTAMP;
+
+DROP INDEX IF EXISTS users_email_uindex;
+DROP INDEX IF EXISTS users_username_uindex;
+DROP INDEX IF EXISTS users_tenant_email_uindex;
+DROP INDEX IF EXISTS users_tenant_username_uindex;
+
+-- Emails and usernames are only unique within a tenant.
+-- Both keep the casing they were registered with but match case-insensitively
+CREATE UNIQUE INDEX IF NOT EXISTS users_tenant_lower_email_uindex
+    ON users (tenant, LOWER(email));
+
+CREATE UNIQUE INDEX IF NOT EXISTS users_tenant_lower_username_uindex
+    ON users (tenant, LOWER(username));
 
 CREATE UNIQUE INDEX IF NOT EXISTS users_id_uindex
     ON users (id);
 
-CREATE UNIQUE INDEX IF NOT EXISTS users_username_uindex
-    ON users (username);
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_token_uindex
+    ON users (email_token);
 
 -- Api token table
 CREATE TABLE IF NOT EXISTS api_token
 (
     id          SERIAL      NOT NULL,
+    tenant      VARCHAR(64) DEFAULT 'default' NOT NULL,
     user_id     INTEGER     NOT NULL,
     name        VARCHAR(32) NOT NULL,
     description TEXT        NOT NULL,
     token       VARCHAR(32) NOT NULL
 );
 
+-- Tokens created before they were scoped to tenants
+ALTER TABLE api_token ADD COLUMN IF NOT EXISTS tenant VARCHAR(64) DEFAULT 'default' NOT NULL;
+
 CREATE UNIQUE INDEX IF NOT EXISTS api_token_id_uindex
     ON api_token (id);
 
 CREATE UNIQUE INDEX IF NOT EXISTS api_token_token_uindex
-    ON api_token (token);
\ No newline at end of file
+    ON api_token (token);
+
+-- Audit log of privileged actions
+CREATE TABLE IF NOT EXISTS audit_log
+(
+    id         SERIAL                  NOT NULL,
+    tenant     VARCHAR(64)             NOT NULL,
+    actor_id   INTEGER                 NOT NULL,
+    target_id  INTEGER,
+    action     VARCHAR(64)             NOT NULL,
+    created_at TIMESTAMP DEFAULT now() NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS audit_log_id_uindex
+    ON audit_log (id);
+
+-- Passkeys registered by users, the credential is stored as JSON
+CREATE TABLE IF NOT EXISTS webauthn_credentials
+(
+    id         SERIAL                  NOT NULL,
+    tenant     VARCHAR(64)             NOT NULL,
+    user_id    INTEGER                 NOT NULL,
+    cred_id    TEXT                    NOT NULL,
+    credential TEXT                    NOT NULL,
+    created_at TIMESTAMP DEFAULT now() NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS webauthn_credentials_id_uindex
+    ON webauthn_credentials (id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS webauthn_credentials_cred_id_uindex
+    ON webauthn_credentials (cred_id);
\ No newline at end of file
@@ -1,6 +1,67 @@
+use actix_web::cookie::SameSite;
 use dotenv::dotenv;
 use rusoto_core::Region;
+use crate::models::user::UserRole;
+use std::collections::HashMap;
 use std::env;
+use std::net::IpAddr;
+
+/// Algorithm used to sign auth tokens
+#[derive(Clone, Copy, PartialEq)]
+pub enum JwtAlgorithm {
+    /// HMAC with a random secret generated at startup
+    Hs256,
+    /// RSA private key signs, public key verifies
+    Rs256,
+    /// P-256 ECDSA private key signs, public key verifies
+    Es256,
+}
+
+/// How log lines are written
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line, for log aggregation
+    Json,
+}
+
+/// Service checking login captchas
+#[derive(Clone, Copy, PartialEq)]
+pub enum CaptchaKind {
+    HCaptcha,
+    ReCaptcha,
+    /// Accepts any token, never use in production
+    Noop,
+}
+
+/// Where emails go
+#[derive(Clone, Copy, PartialEq)]
+pub enum MailKind {
+    Smtp,
+    /// Printed to stdout, for development
+    Console,
+    /// Dropped
+    Noop,
+}
+
+/// Header a trusted proxy passes the client address in
+#[derive(Clone, Copy, PartialEq)]
+pub enum ClientIpHeader {
+    /// Every hop appends the address it saw
+    XForwardedFor,
+    /// Single address set by the proxy, as nginx does
+    XRealIp,
+}
+
+/// Static admin credential for when the database can't be reached
+pub struct BreakGlass {
+    pub email: String,
+    /// Argon2 hash, the password itself is never configured
+    pub password_hash: String,
+    /// How long a break glass session lasts
+    pub minutes: i64,
+}
 
 pub struct Config {
     pub port: u16,
@@ -9,11 +70,123 @@ pub struct Config {
     pub s3_secret_key: String,
     pub s3_bucket: String,
     pub s3_region: Region,
+    /// Paths (starting with `/`) and hosts a login may redirect to
+    pub login_redirect_allowlist: Vec<String>,
+    pub jwt_algorithm: JwtAlgorithm,
+    /// Issuer claim of auth tokens
+    pub jwt_issuer: String,
+    /// Id of the signing key, put in the header of new tokens
+    pub jwt_key_id: String,
+    /// HS256 secret, random on every start when unset
+    pub jwt_secret: Option<String>,
+    /// PEM files, only used by the asymmetric algorithms
+    pub jwt_private_key_path: Option<String>,
+    pub jwt_public_key_path: Option<String>,
+    /// Key id to file of keys rotated out that tokens are still accepted from
+    pub jwt_retired_keys: Vec<(String, String)>,
+    pub log_format: LogFormat,
+    /// Include client IPs in request logs
+    pub log_client_ips: bool,
+    /// Log only the network of a client IP, the last IPv4 octet or last 80 IPv6 bits are zeroed
+    pub log_truncate_ips: bool,
+    /// Oldest an auth token can be, whatever its expiration says. No limit when unset
+    pub max_token_age_hours: Option<u64>,
+    /// Whether tokens without an issue time pass the maximum age check
+    pub accept_tokens_without_iat: bool,
+    /// Whether the instance is served over TLS, enables secure cookies and HSTS
+    pub secure_cookies: bool,
+    /// Headers added to every response
+    pub security_headers: Vec<(String, String)>,
+    /// Redirect plain HTTP requests to HTTPS
+    pub force_https: bool,
+    /// Reverse proxies whose forwarding and request id headers are believed
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Headers the client address is read from, the first one a proxy sent wins
+    pub client_ip_headers: Vec<ClientIpHeader>,
+    /// Days after which a password should be changed, never when unset
+    pub password_max_age_days: Option<i64>,
+    /// Refuse logins with an expired password unless a new one is given
+    pub enforce_password_rotation: bool,
+    /// Name and path of the auth token cookie
+    pub cookie_name: String,
+    pub cookie_path: String,
+    /// SameSite attribute of the auth cookie, Lax unless changed
+    pub cookie_same_site: SameSite,
+    /// Minutes a user has to wait between account data exports
+    pub export_cooldown_minutes: i32,
+    /// Roles whose accounts can only have one active session, a login logs out the others
+    pub single_session_roles: Vec<UserRole>,
+    pub break_glass: Option<BreakGlass>,
+    /// How long an admin impersonation session lasts
+    pub impersonation_minutes: i64,
+    /// Lowercase usernames nobody can register
+    pub reserved_usernames: Vec<String>,
+    /// Items per page of list endpoints when not asked for, and the most that can be asked for
+    pub page_limit_default: i64,
+    pub page_limit_max: i64,
+    /// Request host to tenant, everything belongs to a single tenant when empty
+    pub tenant_hosts: HashMap<String, String>,
+    /// URLs notified of events, and the secret their payloads are signed with
+    pub webhook_urls: Vec<String>,
+    pub webhook_secret: String,
+    /// Failed logins from an IP before a captcha is required, 0 disables captchas
+    pub captcha_threshold: u32,
+    /// How long failed logins count towards the captcha threshold
+    pub captcha_window_minutes: u64,
+    pub captcha_provider: CaptchaKind,
+    pub captcha_secret: String,
+    /// Distinct IPs an account can log in from within the window before it's flagged, 0 disables it
+    pub login_ip_threshold: usize,
+    pub login_ip_window_minutes: u64,
+    /// Flagged accounts have to enter a code mailed to them on their next login, instead of only being logged
+    pub login_ip_require_verification: bool,
+    /// Origin passkeys are bound to, passkeys are disabled when unset
+    pub webauthn_origin: Option<String>,
+    /// Relying party id, the origin's host by default
+    pub webauthn_rp_id: String,
+    pub webauthn_rp_name: String,
+    /// Origins allowed to make cross origin requests, CORS is off when empty
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allowed_headers: Vec<String>,
+    pub cors_exposed_headers: Vec<String>,
+    /// Seconds browsers can cache a preflight for, 0 leaves it to the browser
+    pub cors_max_age: u32,
+    /// Requests per minute allowed from an IP, 0 disables the limit
+    pub rate_limit_per_minute: u32,
+    /// Requests an IP can send at once before the rate applies
+    pub rate_limit_burst: u32,
+    pub mail_backend: MailKind,
+    /// Sender of outgoing emails
+    pub mail_from: String,
+    pub smtp_url: String,
+    /// Database failures in a row before queries fail fast, 0 disables the circuit breaker
+    pub db_breaker_threshold: u32,
+    /// Seconds queries fail fast for before one is tried again
+    pub db_breaker_cooldown_seconds: u64,
+    /// Milliseconds after which a query is logged as slow, 0 disables the warning
+    pub slow_query_ms: u64,
 }
 
 impl Config {
     pub fn new() -> Self {
         dotenv().ok();
+        let secure_cookies = env::var("SECURE_COOKIES").map(|secure| secure.parse().unwrap()).unwrap_or(false);
+        let webhook_urls = comma_list("WEBHOOK_URLS", "");
+
+        // Receivers can't tell real events from forged ones without a secret
+        let webhook_secret = env::var("WEBHOOK_SECRET").unwrap_or_default();
+        if !webhook_urls.is_empty() && webhook_secret.is_empty() {
+            panic!("WEBHOOK_SECRET must be set when WEBHOOK_URLS is");
+        }
+
+        let webauthn_origin = env::var("WEBAUTHN_ORIGIN").ok().filter(|origin| !origin.is_empty());
+        let webauthn_rp_id = match env::var("WEBAUTHN_RP_ID").ok().filter(|id| !id.is_empty()) {
+            Some(id) => id,
+            None => webauthn_origin.as_ref()
+                .map(|origin| url::Url::parse(origin).ok().and_then(|url| url.host_str().map(String::from)).expect("WEBAUTHN_ORIGIN is not a valid URL"))
+                .unwrap_or_default()
+        };
+
         Config {
             port: env::var("PORT").unwrap().parse::<u16>().unwrap(),
             database_url: env::var("DATABASE_URL").unwrap(),
@@ -23,7 +196,311 @@ impl Config {
             s3_region: Region::Custom {
                 name: env::var("S3_REGION").unwrap(),
                 endpoint: env::var("S3_ENDPOINT").unwrap(),
-            }
+            },
+            login_redirect_allowlist: comma_list("LOGIN_REDIRECT_ALLOWLIST", ""),
+            jwt_algorithm: match env::var("JWT_ALGORITHM").unwrap_or_else(|_| "HS256".into()).to_uppercase().as_str() {
+                "HS256" => JwtAlgorithm::Hs256,
+                "RS256" => JwtAlgorithm::Rs256,
+                "ES256" => JwtAlgorithm::Es256,
+                other => panic!("Unsupported JWT_ALGORITHM {}", other)
+            },
+            jwt_issuer: env::var("JWT_ISSUER").unwrap_or_else(|_| "localhost".into()),
+            jwt_key_id: env::var("JWT_KEY_ID").unwrap_or_else(|_| "default".into()),
+            jwt_secret: env::var("JWT_SECRET").ok().filter(|secret| !secret.is_empty()),
+            jwt_private_key_path: env::var("JWT_PRIVATE_KEY").ok(),
+            jwt_public_key_path: env::var("JWT_PUBLIC_KEY").ok(),
+            jwt_retired_keys: env::var("JWT_RETIRED_KEYS")
+                .map(|list| list.split(',')
+                    .filter(|entry| !entry.trim().is_empty())
+                    .map(|entry| {
+                        let mut parts = entry.splitn(2, '=');
+                        let key_id = parts.next().unwrap().trim().to_string();
+                        let path = parts.next().expect("JWT_RETIRED_KEYS entries must look like kid=path").trim().to_string();
+                        (key_id, path)
+                    })
+                    .collect())
+                .unwrap_or_default(),
+            max_token_age_hours: env::var("MAX_TOKEN_AGE_HOURS").ok().map(|hours| hours.parse().unwrap()),
+            accept_tokens_without_iat: env::var("ACCEPT_TOKENS_WITHOUT_IAT").map(|accept| accept.parse().unwrap()).unwrap_or(true),
+            log_format: match env::var("LOG_FORMAT").unwrap_or_else(|_| "text".into()).to_lowercase().as_str() {
+                "text" => LogFormat::Text,
+                "json" => LogFormat::Json,
+                other => panic!("Unsupported LOG_FORMAT {}", other)
+            },
+            log_client_ips: env::var("LOG_CLIENT_IPS").map(|log| log.parse().unwrap()).unwrap_or(false),
+            log_truncate_ips: env::var("LOG_TRUNCATE_IPS").map(|truncate| truncate.parse().unwrap()).unwrap_or(false),
+            secure_cookies,
+            security_headers: security_headers(secure_cookies),
+            force_https: env::var("FORCE_HTTPS").map(|force| force.parse().unwrap()).unwrap_or(false),
+            trusted_proxies: comma_list("TRUSTED_PROXIES", "").iter()
+                .map(|entry| entry.parse().unwrap_or_else(|_| panic!("TRUSTED_PROXIES entry {:?} is not an IP address", entry)))
+                .collect(),
+            client_ip_headers: comma_list("CLIENT_IP_HEADERS", "x-forwarded-for,x-real-ip").iter()
+                .map(|header| match header.to_lowercase().as_str() {
+                    "x-forwarded-for" => ClientIpHeader::XForwardedFor,
+                    "x-real-ip" => ClientIpHeader::XRealIp,
+                    other => panic!("Unsupported CLIENT_IP_HEADERS entry {}", other)
+                })
+                .collect(),
+            password_max_age_days: env::var("PASSWORD_MAX_AGE_DAYS").ok().map(|days| days.parse().unwrap()),
+            enforce_password_rotation: env::var("ENFORCE_PASSWORD_ROTATION").map(|enforce| enforce.parse().unwrap()).unwrap_or(false),
+            cookie_name: cookie_name(),
+            cookie_path: env::var("COOKIE_PATH").unwrap_or_else(|_| "/".into()),
+            cookie_same_site: cookie_same_site(secure_cookies),
+            export_cooldown_minutes: env::var("EXPORT_COOLDOWN_MINUTES").map(|minutes| minutes.parse().unwrap()).unwrap_or(60),
+            single_session_roles: comma_list("SINGLE_SESSION_ROLES", "").iter()
+                .map(|entry| match entry.to_lowercase().as_str() {
+                    "user" => UserRole::User,
+                    "admin" => UserRole::Admin,
+                    other => panic!("Unknown role {} in SINGLE_SESSION_ROLES", other)
+                })
+                .collect(),
+            break_glass: break_glass(),
+            impersonation_minutes: env::var("IMPERSONATION_MINUTES").map(|minutes| minutes.parse().unwrap()).unwrap_or(15),
+            reserved_usernames: comma_list("RESERVED_USERNAMES", "admin,root,support,api").iter()
+                .map(|entry| entry.to_lowercase())
+                .collect(),
+            page_limit_default: env::var("PAGE_LIMIT_DEFAULT").map(|limit| limit.parse().unwrap()).unwrap_or(25),
+            page_limit_max: env::var("PAGE_LIMIT_MAX").map(|limit| limit.parse().unwrap()).unwrap_or(100),
+            tenant_hosts: env::var("TENANT_HOSTS")
+                .map(|list| list.split(',')
+                    .filter(|entry| !entry.trim().is_empty())
+                    .map(|entry| {
+                        let mut parts = entry.splitn(2, '=');
+                        let host = parts.next().unwrap().trim().to_lowercase();
+                        let tenant = parts.next().expect("TENANT_HOSTS entries must look like host=tenant").trim().to_string();
+                        (host, tenant)
+                    })
+                    .collect())
+                .unwrap_or_default(),
+            webhook_urls,
+            webhook_secret,
+            captcha_threshold: env::var("LOGIN_CAPTCHA_THRESHOLD").map(|threshold| threshold.parse().unwrap()).unwrap_or(0),
+            captcha_window_minutes: env::var("LOGIN_CAPTCHA_WINDOW_MINUTES").map(|minutes| minutes.parse().unwrap()).unwrap_or(15),
+            captcha_provider: match env::var("CAPTCHA_PROVIDER").unwrap_or_else(|_| "hcaptcha".into()).to_lowercase().as_str() {
+                "hcaptcha" => CaptchaKind::HCaptcha,
+                "recaptcha" => CaptchaKind::ReCaptcha,
+                "noop" => CaptchaKind::Noop,
+                other => panic!("Unsupported CAPTCHA_PROVIDER {}", other)
+            },
+            captcha_secret: env::var("CAPTCHA_SECRET").unwrap_or_default(),
+            login_ip_threshold: env::var("LOGIN_IP_THRESHOLD").map(|threshold| threshold.parse().unwrap()).unwrap_or(0),
+            login_ip_window_minutes: env::var("LOGIN_IP_WINDOW_MINUTES").map(|minutes| minutes.parse().unwrap()).unwrap_or(60),
+            login_ip_require_verification: env::var("LOGIN_IP_REQUIRE_VERIFICATION").map(|require| require.parse().unwrap()).unwrap_or(false),
+            webauthn_origin,
+            webauthn_rp_id,
+            webauthn_rp_name: env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "kawaii".into()),
+            cors_allowed_origins: comma_list("CORS_ALLOWED_ORIGINS", ""),
+            cors_allowed_headers: comma_list("CORS_ALLOWED_HEADERS", "Content-Type"),
+            cors_exposed_headers: comma_list("CORS_EXPOSED_HEADERS", "X-Request-Id,Retry-After"),
+            cors_max_age: env::var("CORS_MAX_AGE").map(|age| age.parse().unwrap()).unwrap_or(600),
+            rate_limit_per_minute: env::var("RATE_LIMIT_PER_MINUTE").map(|rate| rate.parse().unwrap()).unwrap_or(0),
+            rate_limit_burst: env::var("RATE_LIMIT_BURST").map(|burst| burst.parse().unwrap()).unwrap_or(60),
+            mail_backend: match env::var("MAIL_BACKEND").unwrap_or_else(|_| "noop".into()).to_lowercase().as_str() {
+                "smtp" => MailKind::Smtp,
+                "console" => MailKind::Console,
+                "noop" => MailKind::Noop,
+                other => panic!("Unsupported MAIL_BACKEND {}", other)
+            },
+            mail_from: env::var("MAIL_FROM").unwrap_or_else(|_| "kawaii <noreply@localhost>".into()),
+            smtp_url: env::var("SMTP_URL").unwrap_or_default(),
+            db_breaker_threshold: env::var("DB_BREAKER_THRESHOLD").map(|threshold| threshold.parse().unwrap()).unwrap_or(5),
+            db_breaker_cooldown_seconds: env::var("DB_BREAKER_COOLDOWN_SECONDS").map(|seconds| seconds.parse().unwrap()).unwrap_or(30),
+            slow_query_ms: env::var("SLOW_QUERY_MS").map(|ms| ms.parse().unwrap()).unwrap_or(500),
         }
     }
+}
+
+#[cfg(test)]
+impl Config {
+    /// Defaults for tests, without reading the environment. Only the database and S3 settings point nowhere
+    pub fn for_test() -> Self {
+        Config {
+            port: 0,
+            database_url: String::new(),
+            s3_access_key: String::new(),
+            s3_secret_key: String::new(),
+            s3_bucket: String::new(),
+            s3_region: Region::Custom { name: "test".into(), endpoint: "http://localhost".into() },
+            login_redirect_allowlist: Vec::new(),
+            jwt_algorithm: JwtAlgorithm::Hs256,
+            jwt_issuer: "localhost".into(),
+            jwt_key_id: "default".into(),
+            jwt_secret: Some("test secret".into()),
+            jwt_private_key_path: None,
+            jwt_public_key_path: None,
+            jwt_retired_keys: Vec::new(),
+            log_format: LogFormat::Text,
+            log_client_ips: false,
+            log_truncate_ips: false,
+            max_token_age_hours: None,
+            accept_tokens_without_iat: true,
+            secure_cookies: false,
+            security_headers: Vec::new(),
+            force_https: false,
+            trusted_proxies: Vec::new(),
+            client_ip_headers: vec![ClientIpHeader::XForwardedFor, ClientIpHeader::XRealIp],
+            password_max_age_days: None,
+            enforce_password_rotation: false,
+            cookie_name: "auth-token".into(),
+            cookie_path: "/".into(),
+            cookie_same_site: SameSite::Lax,
+            export_cooldown_minutes: 60,
+            single_session_roles: Vec::new(),
+            break_glass: None,
+            impersonation_minutes: 15,
+            reserved_usernames: vec!["admin".into()],
+            page_limit_default: 25,
+            page_limit_max: 100,
+            tenant_hosts: HashMap::new(),
+            webhook_urls: Vec::new(),
+            webhook_secret: String::new(),
+            captcha_threshold: 0,
+            captcha_window_minutes: 15,
+            captcha_provider: CaptchaKind::Noop,
+            captcha_secret: String::new(),
+            login_ip_threshold: 0,
+            login_ip_window_minutes: 60,
+            login_ip_require_verification: false,
+            webauthn_origin: None,
+            webauthn_rp_id: String::new(),
+            webauthn_rp_name: "kawaii".into(),
+            cors_allowed_origins: Vec::new(),
+            cors_allowed_headers: Vec::new(),
+            cors_exposed_headers: Vec::new(),
+            cors_max_age: 0,
+            rate_limit_per_minute: 0,
+            rate_limit_burst: 60,
+            mail_backend: MailKind::Noop,
+            mail_from: "kawaii <noreply@localhost>".into(),
+            smtp_url: String::new(),
+            db_breaker_threshold: 0,
+            db_breaker_cooldown_seconds: 30,
+            slow_query_ms: 0,
+        }
+    }
+}
+
+/// Read the break glass credential, disabled unless both email and hash are set
+fn break_glass() -> Option<BreakGlass> {
+    let email = env::var("BREAK_GLASS_EMAIL").ok().filter(|email| !email.is_empty())?;
+    let password_hash = env::var("BREAK_GLASS_PASSWORD_HASH").ok().filter(|hash| !hash.is_empty())?;
+
+    if !password_hash.starts_with("$argon2") {
+        panic!("BREAK_GLASS_PASSWORD_HASH has to be an argon2 hash");
+    }
+
+    Some(BreakGlass {
+        email,
+        password_hash,
+        minutes: env::var("BREAK_GLASS_MINUTES").map(|minutes| minutes.parse().unwrap()).unwrap_or(30),
+    })
+}
+
+/// Read a comma separated list, using the default when unset
+fn comma_list(var: &str, default: &str) -> Vec<String> {
+    env::var(var)
+        .unwrap_or_else(|_| default.into())
+        .split(',')
+        .map(|entry| entry.trim().to_string())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+/// Read the auth cookie name, which has to be a valid cookie token (RFC 6265)
+fn cookie_name() -> String {
+    let name = env::var("COOKIE_NAME").unwrap_or_else(|_| "auth-token".into());
+
+    if !is_valid_cookie_name(&name) {
+        panic!("COOKIE_NAME {:?} is not a valid cookie name", name);
+    }
+
+    name
+}
+
+/// Cookie names are HTTP tokens (RFC 6265), so no separators, spaces or control characters
+fn is_valid_cookie_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c))
+}
+
+fn cookie_same_site(secure: bool) -> SameSite {
+    let same_site = match env::var("COOKIE_SAME_SITE").unwrap_or_default().to_lowercase().as_str() {
+        "" | "lax" => SameSite::Lax,
+        "strict" => SameSite::Strict,
+        "none" => SameSite::None,
+        other => panic!("Unsupported COOKIE_SAME_SITE {}", other)
+    };
+
+    // Browsers drop SameSite=None cookies that aren't also Secure
+    if same_site == SameSite::None && !secure {
+        panic!("COOKIE_SAME_SITE=none requires SECURE_COOKIES");
+    }
+
+    same_site
+}
+
+/// Security headers with their defaults, each can be overridden or disabled with an empty value
+fn security_headers(secure: bool) -> Vec<(String, String)> {
+    security_headers_from(secure, |var| env::var(var).ok())
+}
+
+/// Security headers with the overrides `var` returns by environment variable name
+fn security_headers_from(secure: bool, var: impl Fn(&str) -> Option<String>) -> Vec<(String, String)> {
+    let mut headers = vec![
+        ("X-Frame-Options", "X_FRAME_OPTIONS", "DENY"),
+        ("Referrer-Policy", "REFERRER_POLICY", "no-referrer"),
+        ("X-Content-Type-Options", "X_CONTENT_TYPE_OPTIONS", "nosniff"),
+    ];
+
+    // HSTS over plain HTTP would lock browsers out of a local instance
+    if secure {
+        headers.push(("Strict-Transport-Security", "STRICT_TRANSPORT_SECURITY", "max-age=31536000; includeSubDomains"));
+    }
+
+    headers.into_iter()
+        .filter_map(|(header, name, default)| match var(name) {
+            Some(value) if value.is_empty() => None,
+            Some(value) => Some((header.to_string(), value)),
+            None => Some((header.to_string(), default.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(headers: &[(String, String)]) -> Vec<&str> {
+        headers.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn cookie_names_must_be_tokens() {
+        assert!(is_valid_cookie_name("auth-token"));
+        assert!(is_valid_cookie_name("__Host-kawaii"));
+
+        for name in ["", "auth token", "auth=token", "auth;token", "auth,token", "\"auth\"", "auth/token", "tökén", "auth\ttoken"] {
+            assert!(!is_valid_cookie_name(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn hsts_is_only_sent_with_secure_cookies() {
+        assert_eq!(names(&security_headers_from(false, |_| None)), ["X-Frame-Options", "Referrer-Policy", "X-Content-Type-Options"]);
+        assert!(names(&security_headers_from(true, |_| None)).contains(&"Strict-Transport-Security"));
+    }
+
+    #[test]
+    fn security_headers_can_be_overridden_or_disabled() {
+        let headers = security_headers_from(false, |var| match var {
+            "X_FRAME_OPTIONS" => Some("SAMEORIGIN".into()),
+            "REFERRER_POLICY" => Some(String::new()),
+            _ => None
+        });
+
+        assert_eq!(headers, [
+            ("X-Frame-Options".to_string(), "SAMEORIGIN".to_string()),
+            ("X-Content-Type-Options".to_string(), "nosniff".to_string())
+        ]);
+    }
 }
\ No newline at end of file
@@ -1,125 +1,541 @@
-use crate::{models};
+use crate::{config::Config, models};
 
+use futures::Future;
 use sqlx::postgres::PgPoolOptions;
-use sqlx::Row;
+use sqlx::{Done, Row};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Postgres error code for a unique constraint violation
+const UNIQUE_VIOLATION: &str = "23505";
+
+/// Unique index on the email of a user within a tenant
+pub const USER_EMAIL_CONSTRAINT: &str = "users_tenant_lower_email_uindex";
+
+/// Errors returned by the database layer
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// No row matched the query
+    NotFound,
+    /// The query would have violated the named unique constraint
+    Conflict(Option<String>),
+    /// The database itself failed, e.g. the connection was lost
+    Backend(sqlx::Error),
+    /// The database failed too often recently, so the query wasn't sent
+    Unavailable,
+}
+
+impl From<sqlx::Error> for DatabaseError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => DatabaseError::NotFound,
+            sqlx::Error::Database(ref err) if err.code().as_deref() == Some(UNIQUE_VIOLATION) => DatabaseError::Conflict(
+                err.try_downcast_ref::<sqlx::postgres::PgDatabaseError>().and_then(|err| err.constraint()).map(String::from)
+            ),
+            error => DatabaseError::Backend(error)
+        }
+    }
+}
+
+impl std::fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatabaseError::NotFound => write!(f, "row not found"),
+            DatabaseError::Conflict(Some(constraint)) => write!(f, "unique constraint {} violated", constraint),
+            DatabaseError::Conflict(None) => write!(f, "unique constraint violated"),
+            DatabaseError::Backend(error) => write!(f, "database error: {}", error),
+            DatabaseError::Unavailable => write!(f, "database unavailable")
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+/// Whether the circuit breaker lets a query through
+#[derive(PartialEq, Debug)]
+enum Permit {
+    Query,
+    /// First query after the cooldown, deciding whether the breaker closes
+    Probe,
+    Denied
+}
+
+struct BreakerState {
+    failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool
+}
+
+/// Stops sending queries to a database that keeps failing,
+/// letting a single one through after the cooldown to check if it's back
+struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>
+}
+
+impl CircuitBreaker {
+    /// Whether a query may be sent
+    fn allow(&self) -> Permit {
+        if self.threshold == 0 {
+            return Permit::Query;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        match state.opened_at {
+            None => Permit::Query,
+            Some(opened_at) if opened_at.elapsed() >= self.cooldown && !state.probing => {
+                state.probing = true;
+                Permit::Probe
+            },
+            Some(_) => Permit::Denied
+        }
+    }
+    /// Let another query probe, for a probe that was dropped before it finished
+    fn cancel_probe(&self) {
+        self.state.lock().unwrap().probing = false;
+    }
+    fn record<T>(&self, result: &Result<T, DatabaseError>) {
+        if self.threshold == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.probing = false;
+
+        match result {
+            Err(DatabaseError::Backend(_)) => {
+                state.failures += 1;
+                // A failed probe starts another cooldown
+                if state.failures >= self.threshold || state.opened_at.is_some() {
+                    state.opened_at = Some(Instant::now());
+                }
+            },
+            _ => {
+                state.failures = 0;
+                state.opened_at = None;
+            }
+        }
+    }
+    /// Seconds until the next probe while the breaker is open
+    fn retry_after(&self) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        state.opened_at.map(|opened_at| self.cooldown.saturating_sub(opened_at.elapsed()).as_secs().max(1))
+    }
+}
+
+/// Releases the probe slot when a probing query is dropped, e.g. because the client disconnected
+struct ProbeGuard<'a> {
+    breaker: &'a CircuitBreaker,
+    armed: bool
+}
+
+impl Drop for ProbeGuard<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.breaker.cancel_probe();
+        }
+    }
+}
 
 pub struct Database {
-    pool: sqlx::Pool<sqlx::Postgres>
+    pool: sqlx::Pool<sqlx::Postgres>,
+    breaker: CircuitBreaker,
+    /// Queries taking longer are logged, never when unset
+    slow_query: Option<Duration>
 }
 
 impl Database {
-    pub async fn new(max_connections: u32, url: &str) -> Self {
+    /// Connect to the database. After `DB_BREAKER_THRESHOLD` failures in a row queries fail
+    /// right away for `DB_BREAKER_COOLDOWN_SECONDS`, a threshold of 0 always sends them
+    pub async fn new(max_connections: u32, config: &Config) -> Self {
         Database {
             pool: PgPoolOptions::new()
                         .max_connections(max_connections)
-                        .connect(url).await
-                        .expect("Could not initialize connection")
+                        .connect(&config.database_url).await
+                        .expect("Could not initialize connection"),
+            breaker: CircuitBreaker {
+                threshold: config.db_breaker_threshold,
+                cooldown: Duration::from_secs(config.db_breaker_cooldown_seconds),
+                state: Mutex::new(BreakerState { failures: 0, opened_at: None, probing: false })
+            },
+            slow_query: (config.slow_query_ms > 0).then(|| Duration::from_millis(config.slow_query_ms))
         }
     }
-    /// Creates a user from a user creation form
-    pub async fn create_user(&self, form: &models::user::UserCreateForm) -> Result<(), sqlx::Error> {
-        sqlx::query("INSERT INTO users (email, username, password) VALUES ($1, $2, $3)")
-            .bind(&form.email)
-            .bind(&form.username)
-            .bind(&form.password)
-            .execute(&self.pool)
-            .await?;
-
-        Ok(())
-    }
-    /// Gets user info from database by email
-    pub async fn get_user_by_email(&self, email: &str) -> Result<models::user::UserData, sqlx::Error> {
-        sqlx::query("SELECT id, email, username, password, verified, role FROM users WHERE email = $1")
-            .bind(email)
-            .try_map(user_map)
-            .fetch_one(&self.pool)
-            .await
+    /// Run a query through the circuit breaker, warning when it's slow.
+    /// The warning is logged in the caller's span, so it carries the id of the request that ran the query
+    async fn guarded<T>(&self, name: &'static str, query: impl Future<Output = Result<T, DatabaseError>>) -> Result<T, DatabaseError> {
+        let mut guard = match self.breaker.allow() {
+            Permit::Denied => return Err(DatabaseError::Unavailable),
+            permit => ProbeGuard { breaker: &self.breaker, armed: permit == Permit::Probe }
+        };
+
+        let start = Instant::now();
+        let result = query.await;
+        guard.armed = false;
+        self.breaker.record(&result);
+
+        let elapsed = start.elapsed();
+        if self.slow_query.is_some_and(|threshold| elapsed >= threshold) {
+            tracing::warn!(query = name, elapsed_ms = elapsed.as_millis() as u64, "slow database query");
+        }
+
+        result
+    }
+    /// Seconds clients should wait before retrying, `None` while the database is considered up
+    pub fn retry_after(&self) -> Option<u64> {
+        self.breaker.retry_after()
+    }
+    /// Creates a user in a tenant from a user creation form, returning its id
+    pub async fn create_user(&self, tenant: &str, form: &models::user::UserCreateForm) -> Result<i32, DatabaseError> {
+        self.guarded("create_user", async {
+            let row = sqlx::query("INSERT INTO users (tenant, email, username, password) VALUES ($1, $2, $3, $4) RETURNING id")
+                .bind(tenant)
+                .bind(&form.email)
+                .bind(&form.username)
+                .bind(&form.password)
+                .fetch_one(&self.pool)
+                .await?;
+
+            Ok(row.get("id"))
+        }).await
+    }
+    /// Gets user info from database by email, ignoring case
+    pub async fn get_user_by_email(&self, tenant: &str, email: &str) -> Result<models::user::UserData, DatabaseError> {
+        self.guarded("get_user_by_email", async {
+            sqlx::query("SELECT id, tenant, email, username, password, verified, role, token_version, password_changed_at FROM users WHERE tenant = $1 AND LOWER(email) = LOWER($2)")
+                .bind(tenant)
+                .bind(email)
+                .try_map(user_map)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(DatabaseError::from)
+        }).await
     }
     /// Gets user info from database by id
-    pub async fn get_user_by_id(&self, id: u32) -> Result<models::user::UserData, sqlx::Error> {
-        sqlx::query("SELECT id, email, username, password, verified, role FROM users WHERE id = $1")
-            .bind(id)
-            .try_map(user_map)
-            .fetch_one(&self.pool)
-            .await
+    pub async fn get_user_by_id(&self, tenant: &str, id: u32) -> Result<models::user::UserData, DatabaseError> {
+        self.guarded("get_user_by_id", async {
+            sqlx::query("SELECT id, tenant, email, username, password, verified, role, token_version, password_changed_at FROM users WHERE tenant = $1 AND id = $2")
+                .bind(tenant)
+                .bind(id)
+                .try_map(user_map)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(DatabaseError::from)
+        }).await
     }
-    /// Gets user info from database by username
-    pub async fn get_user_by_username(&self, username: &str) -> Result<models::user::UserData, sqlx::Error> {
-        sqlx::query("SELECT id, email, username, password, verified, role FROM users WHERE username = $1")
-            .bind(username)
-            .try_map(user_map)
-            .fetch_one(&self.pool)
-            .await
+    /// Gets user info from database by username, ignoring case
+    pub async fn get_user_by_username(&self, tenant: &str, username: &str) -> Result<models::user::UserData, DatabaseError> {
+        self.guarded("get_user_by_username", async {
+            sqlx::query("SELECT id, tenant, email, username, password, verified, role, token_version, password_changed_at FROM users WHERE tenant = $1 AND LOWER(username) = LOWER($2)")
+                .bind(tenant)
+                .bind(username)
+                .try_map(user_map)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(DatabaseError::from)
+        }).await
     }
-    /// Change a password for a user id
-    pub async fn change_password(&self, id: u32, password: &str) -> Result<(), sqlx::Error> {
-        sqlx::query("UPDATE users SET password = $1 WHERE id = $2")
-            .bind(password)
-            .bind(password)
-            .execute(&self.pool)
-            .await?;
+    /// Gets a page of the users of a tenant, oldest first
+    pub async fn get_users(&self, tenant: &str, offset: i64, limit: i64) -> Result<Vec<models::user::UserData>, DatabaseError> {
+        self.guarded("get_users", async {
+            sqlx::query("SELECT id, tenant, email, username, password, verified, role, token_version, password_changed_at FROM users WHERE tenant = $1 ORDER BY id LIMIT $2 OFFSET $3")
+                .bind(tenant)
+                .bind(limit)
+                .bind(offset)
+                .try_map(user_map)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(DatabaseError::from)
+        }).await
+    }
+    /// Counts the users of a tenant
+    pub async fn get_user_count(&self, tenant: &str) -> Result<i64, DatabaseError> {
+        self.guarded("get_user_count", async {
+            let row: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users WHERE tenant = $1")
+                .bind(tenant)
+                .fetch_one(&self.pool)
+                .await?;
+
+            Ok(row.0)
+        }).await
+    }
+    /// Change the password of a user in a tenant
+    pub async fn change_password(&self, tenant: &str, id: u32, password: &str) -> Result<(), DatabaseError> {
+        self.guarded("change_password", async {
+            let done = sqlx::query("UPDATE users SET password = $1, password_changed_at = (now() AT TIME ZONE 'utc') WHERE tenant = $2 AND id = $3")
+                .bind(password)
+                .bind(tenant)
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+
+            if done.rows_affected() == 0 {
+                return Err(DatabaseError::NotFound);
+            }
+
+            Ok(())
+        }).await
+    }
+    /// Store an email waiting for confirmation, replacing the one requested before
+    pub async fn set_pending_email(&self, tenant: &str, id: i32, email: &str, token_hash: &str, expires_at: chrono::NaiveDateTime) -> Result<(), DatabaseError> {
+        self.guarded("set_pending_email", async {
+            let done = sqlx::query("UPDATE users SET pending_email = $1, email_token = $2, email_token_expires_at = $3 WHERE tenant = $4 AND id = $5")
+                .bind(email)
+                .bind(token_hash)
+                .bind(expires_at)
+                .bind(tenant)
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+
+            if done.rows_affected() == 0 {
+                return Err(DatabaseError::NotFound);
+            }
+
+            Ok(())
+        }).await
+    }
+    /// Make the pending email the token was sent to the email of its user, returning the user id.
+    /// `Conflict` when another account took the email in the meantime
+    pub async fn confirm_pending_email(&self, tenant: &str, token_hash: &str) -> Result<i32, DatabaseError> {
+        self.guarded("confirm_pending_email", async {
+            let row = sqlx::query("UPDATE users SET email = pending_email, pending_email = NULL, email_token = NULL, email_token_expires_at = NULL WHERE tenant = $1 AND email_token = $2 AND email_token_expires_at > (now() AT TIME ZONE 'utc') RETURNING id")
+                .bind(tenant)
+                .bind(token_hash)
+                .fetch_one(&self.pool)
+                .await?;
+
+            Ok(row.get("id"))
+        }).await
+    }
+    /// Invalidate all auth tokens of a user, returning the version new tokens have to carry
+    pub async fn bump_token_version(&self, tenant: &str, id: i32) -> Result<i32, DatabaseError> {
+        self.guarded("bump_token_version", async {
+            let row = sqlx::query("UPDATE users SET token_version = token_version + 1 WHERE tenant = $1 AND id = $2 RETURNING token_version")
+                .bind(tenant)
+                .bind(id)
+                .fetch_one(&self.pool)
+                .await?;
+
+            Ok(row.get("token_version"))
+        }).await
+    }
+    /// Delete a user together with their api tokens and passkeys, recording `actor_id` in the audit log
+    /// in the same transaction so a deletion is never left unaudited
+    pub async fn delete_user(&self, tenant: &str, id: i32, actor_id: i32) -> Result<(), DatabaseError> {
+        self.guarded("delete_user", async {
+            let mut tx = self.pool.begin().await?;
+
+            sqlx::query("DELETE FROM api_token WHERE tenant = $1 AND user_id = $2")
+                .bind(tenant)
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
+
+            sqlx::query("DELETE FROM webauthn_credentials WHERE tenant = $1 AND user_id = $2")
+                .bind(tenant)
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
+
+            let done = sqlx::query("DELETE FROM users WHERE tenant = $1 AND id = $2")
+                .bind(tenant)
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
+
+            if done.rows_affected() == 0 {
+                return Err(DatabaseError::NotFound);
+            }
+
+            sqlx::query("INSERT INTO audit_log (tenant, actor_id, target_id, action) VALUES ($1, $2, $3, 'user.delete')")
+                .bind(tenant)
+                .bind(actor_id)
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
 
-        Ok(())
+            tx.commit().await?;
+
+            Ok(())
+        }).await
     }
-    /// Create a new token
-    pub async fn create_token(&self, user_id: u32, name: &str, description: &str, token: &str) -> Result<(), sqlx::Error> {
-        sqlx::query("INSERT INTO api_token (user_id, name, description, token) VALUES ($1, $2, $3, $4)")
-            .bind(user_id)
-            .bind(name)
-            .bind(description)
-            .bind(token)
-            .execute(&self.pool)
-            .await?;
+    /// Create a new token for a user in a tenant
+    pub async fn create_token(&self, tenant: &str, user_id: u32, name: &str, description: &str, token: &str) -> Result<(), DatabaseError> {
+        self.guarded("create_token", async {
+            sqlx::query("INSERT INTO api_token (tenant, user_id, name, description, token) VALUES ($1, $2, $3, $4, $5)")
+                .bind(tenant)
+                .bind(user_id)
+                .bind(name)
+                .bind(description)
+                .bind(token)
+                .execute(&self.pool)
+                .await?;
 
-        Ok(())
+            Ok(())
+        }).await
     }
     /// Delete a token by its id
-    pub async fn delete_token_by_id(&self, token_id: u32) -> Result<(), sqlx::Error> {
-        sqlx::query("DELETE FROM api_token WHERE id = $1")
-            .bind(token_id)
-            .execute(&self.pool)
-            .await?;
+    pub async fn delete_token_by_id(&self, tenant: &str, token_id: u32) -> Result<(), DatabaseError> {
+        self.guarded("delete_token_by_id", async {
+            let done = sqlx::query("DELETE FROM api_token WHERE tenant = $1 AND id = $2")
+                .bind(tenant)
+                .bind(token_id)
+                .execute(&self.pool)
+                .await?;
+
+            if done.rows_affected() == 0 {
+                return Err(DatabaseError::NotFound);
+            }
 
-        Ok(())
+            Ok(())
+        }).await
     }
     /// Get a token by its id
-    pub async fn get_token_by_id(&self, token_id: u32) -> Result<models::token::TokenData, sqlx::Error> {
-        sqlx::query("SELECT name, description, token FROM api_token WHERE id = $1")
-            .bind(token_id)
-            .try_map(token_map)
-            .fetch_one(&self.pool)
-            .await
+    pub async fn get_token_by_id(&self, tenant: &str, token_id: u32) -> Result<models::token::TokenData, DatabaseError> {
+        self.guarded("get_token_by_id", async {
+            sqlx::query("SELECT name, description, token FROM api_token WHERE tenant = $1 AND id = $2")
+                .bind(tenant)
+                .bind(token_id)
+                .try_map(token_map)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(DatabaseError::from)
+        }).await
     }
     /// Get all tokens for a user from their id
-    pub async fn get_all_tokens(&self, user_id: u32) -> Result<Vec<models::token::TokenData>, sqlx::Error> {
-        sqlx::query("SELECT name, description, token FROM api_token WHERE user_id = $1")
-            .bind(user_id)
-            .try_map(token_map)
-            .fetch_all(&self.pool)
-            .await
+    pub async fn get_all_tokens(&self, tenant: &str, user_id: u32) -> Result<Vec<models::token::TokenData>, DatabaseError> {
+        self.guarded("get_all_tokens", async {
+            sqlx::query("SELECT name, description, token FROM api_token WHERE tenant = $1 AND user_id = $2")
+                .bind(tenant)
+                .bind(user_id)
+                .try_map(token_map)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(DatabaseError::from)
+        }).await
     }
     /// Get the amount of tokens a user has
-    pub async fn get_token_count(&self, user_id: u32)-> Result<i32, sqlx::Error> {
-        let row: (i32,) = sqlx::query_as("SELECT COUNT(*) FROM api_token WHERE user_id = $1")
-            .bind(user_id)
-            .fetch_one(&self.pool)
-            .await?;
-        
-        Ok(row.0)
+    pub async fn get_token_count(&self, tenant: &str, user_id: u32)-> Result<i32, DatabaseError> {
+        self.guarded("get_token_count", async {
+            let row: (i32,) = sqlx::query_as("SELECT COUNT(*) FROM api_token WHERE tenant = $1 AND user_id = $2")
+                .bind(tenant)
+                .bind(user_id)
+                .fetch_one(&self.pool)
+                .await?;
+
+            Ok(row.0)
+        }).await
     }
     /// Check if a token already exists in the database.
-    /// Return (name_exists, token_exists)
-    pub async fn check_token_exist(&self, token: &str, name: &str) -> Result<(bool, bool), sqlx::Error> {
-        let rows = sqlx::query("SELECT EXISTS(SELECT 1 FROM api_token WHERE name = $1) UNION ALL SELECT EXISTS(SELECT 1 FROM api_token WHERE token = $2)")
-            .bind(name)
-            .bind(token)
-            .try_map(|row: sqlx::postgres::PgRow| -> Result<bool, sqlx::Error> {
-                Ok(row.get("count"))
-            })
-            .fetch_all(&self.pool)
-            .await?;
+    /// Return (name_exists, token_exists), names are per tenant but tokens are unique across all of them
+    pub async fn check_token_exist(&self, tenant: &str, token: &str, name: &str) -> Result<(bool, bool), DatabaseError> {
+        self.guarded("check_token_exist", async {
+            let rows = sqlx::query("SELECT EXISTS(SELECT 1 FROM api_token WHERE tenant = $1 AND name = $2) UNION ALL SELECT EXISTS(SELECT 1 FROM api_token WHERE token = $3)")
+                .bind(tenant)
+                .bind(name)
+                .bind(token)
+                .try_map(|row: sqlx::postgres::PgRow| -> Result<bool, sqlx::Error> {
+                    Ok(row.get("count"))
+                })
+                .fetch_all(&self.pool)
+                .await?;
             
-        Ok((rows[0], rows[1]))
+            Ok((rows[0], rows[1]))
+        }).await
+    }
+    /// Insert imported users in one transaction, returning whether each record was inserted.
+    /// Records clashing with an existing email or username are skipped
+    pub async fn import_users(&self, tenant: &str, records: &[&models::user::UserImportRecord]) -> Result<Vec<bool>, DatabaseError> {
+        self.guarded("import_users", async {
+            let mut tx = self.pool.begin().await?;
+            let mut inserted = Vec::with_capacity(records.len());
+
+            for record in records {
+                let row = sqlx::query("INSERT INTO users (tenant, email, username, password, role) VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING RETURNING id")
+                    .bind(tenant)
+                    .bind(&record.email)
+                    .bind(&record.username)
+                    .bind(&record.password_hash)
+                    .bind(&record.role)
+                    .fetch_optional(&mut tx)
+                    .await?;
+
+                inserted.push(row.is_some());
+            }
+
+            tx.commit().await?;
+
+            Ok(inserted)
+        }).await
+    }
+    /// Whether a user did an action within the last few minutes
+    pub async fn has_recent_audit_log(&self, tenant: &str, actor_id: i32, action: &str, minutes: i32) -> Result<bool, DatabaseError> {
+        self.guarded("has_recent_audit_log", async {
+            let row: (bool,) = sqlx::query_as("SELECT EXISTS(SELECT 1 FROM audit_log WHERE tenant = $1 AND actor_id = $2 AND action = $3 AND created_at > now() - make_interval(mins => $4))")
+                .bind(tenant)
+                .bind(actor_id)
+                .bind(action)
+                .bind(minutes)
+                .fetch_one(&self.pool)
+                .await?;
+
+            Ok(row.0)
+        }).await
+    }
+    /// Store a newly registered passkey
+    pub async fn create_webauthn_credential(&self, tenant: &str, user_id: i32, cred_id: &str, credential: &str) -> Result<(), DatabaseError> {
+        self.guarded("create_webauthn_credential", async {
+            sqlx::query("INSERT INTO webauthn_credentials (tenant, user_id, cred_id, credential) VALUES ($1, $2, $3, $4)")
+                .bind(tenant)
+                .bind(user_id)
+                .bind(cred_id)
+                .bind(credential)
+                .execute(&self.pool)
+                .await?;
+
+            Ok(())
+        }).await
+    }
+    /// Get the passkeys of a user as stored JSON
+    pub async fn get_webauthn_credentials(&self, tenant: &str, user_id: i32) -> Result<Vec<String>, DatabaseError> {
+        self.guarded("get_webauthn_credentials", async {
+            let rows: Vec<(String,)> = sqlx::query_as("SELECT credential FROM webauthn_credentials WHERE tenant = $1 AND user_id = $2")
+                .bind(tenant)
+                .bind(user_id)
+                .fetch_all(&self.pool)
+                .await?;
+
+            Ok(rows.into_iter().map(|row| row.0).collect())
+        }).await
+    }
+    /// Replace a stored passkey, used to keep its signature counter current
+    pub async fn update_webauthn_credential(&self, tenant: &str, cred_id: &str, credential: &str) -> Result<(), DatabaseError> {
+        self.guarded("update_webauthn_credential", async {
+            sqlx::query("UPDATE webauthn_credentials SET credential = $1 WHERE tenant = $2 AND cred_id = $3")
+                .bind(credential)
+                .bind(tenant)
+                .bind(cred_id)
+                .execute(&self.pool)
+                .await?;
+
+            Ok(())
+        }).await
+    }
+    /// Record a privileged action in the audit log
+    pub async fn insert_audit_log(&self, tenant: &str, actor_id: i32, target_id: Option<i32>, action: &str) -> Result<(), DatabaseError> {
+        self.guarded("insert_audit_log", async {
+            sqlx::query("INSERT INTO audit_log (tenant, actor_id, target_id, action) VALUES ($1, $2, $3, $4)")
+                .bind(tenant)
+                .bind(actor_id)
+                .bind(target_id)
+                .bind(action)
+                .execute(&self.pool)
+                .await?;
+
+            Ok(())
+        }).await
     }
 }
 
@@ -127,11 +543,14 @@ impl Database {
 fn user_map(row: sqlx::postgres::PgRow) -> Result<models::user::UserData, sqlx::Error> {
     Ok(models::user::UserData {
         id: row.get("id"),
+        tenant: row.get("tenant"),
         email: row.get("email"),
         username: row.get("username"),
         verified: row.get("verified"),
         password: row.get("password"),
-        role: row.get("role")
+        role: row.get("role"),
+        token_version: row.get("token_version"),
+        password_changed_at: row.get("password_changed_at")
     })
 }
 
@@ -142,4 +561,150 @@ fn token_map(row: sqlx::postgres::PgRow) -> Result<models::token::TokenData, sql
         description: row.get("description"),
         token: row.get("token"),
     })
+}
+
+#[cfg(test)]
+impl Database {
+    /// Database nothing listens for, every query fails quickly with `Backend`
+    pub fn unreachable() -> Self {
+        Database {
+            pool: PgPoolOptions::new()
+                        .connect_timeout(Duration::from_millis(50))
+                        .connect_lazy("postgres://127.0.0.1:1/kawaii")
+                        .expect("Could not initialize connection"),
+            breaker: CircuitBreaker {
+                threshold: 0,
+                cooldown: Duration::from_secs(0),
+                state: Mutex::new(BreakerState { failures: 0, opened_at: None, probing: false })
+            },
+            slow_query: None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqlx_errors_are_mapped() {
+        assert!(matches!(DatabaseError::from(sqlx::Error::RowNotFound), DatabaseError::NotFound));
+        assert!(matches!(DatabaseError::from(sqlx::Error::PoolTimedOut), DatabaseError::Backend(sqlx::Error::PoolTimedOut)));
+    }
+
+    fn breaker(threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            state: Mutex::new(BreakerState { failures: 0, opened_at: None, probing: false })
+        }
+    }
+
+    fn failure() -> Result<(), DatabaseError> {
+        Err(DatabaseError::Backend(sqlx::Error::PoolTimedOut))
+    }
+
+    #[test]
+    fn opens_after_repeated_failures() {
+        let breaker = breaker(3, Duration::from_secs(60));
+
+        for _ in 0..2 {
+            assert_eq!(breaker.allow(), Permit::Query);
+            breaker.record(&failure());
+        }
+        assert_eq!(breaker.allow(), Permit::Query);
+        breaker.record(&failure());
+
+        assert_eq!(breaker.allow(), Permit::Denied);
+        assert!(breaker.retry_after().is_some());
+    }
+
+    #[test]
+    fn not_found_is_not_a_failure() {
+        let breaker = breaker(1, Duration::from_secs(60));
+        breaker.record::<()>(&Err(DatabaseError::NotFound));
+
+        assert_eq!(breaker.allow(), Permit::Query);
+    }
+
+    #[test]
+    fn successful_probe_closes_after_cooldown() {
+        let breaker = breaker(1, Duration::from_millis(10));
+        breaker.record(&failure());
+        assert_eq!(breaker.allow(), Permit::Denied);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(breaker.allow(), Permit::Probe);
+        // Only one probe at a time
+        assert_eq!(breaker.allow(), Permit::Denied);
+
+        breaker.record(&Ok(()));
+        assert_eq!(breaker.allow(), Permit::Query);
+        assert!(breaker.retry_after().is_none());
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let breaker = breaker(1, Duration::from_millis(10));
+        breaker.record(&failure());
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(breaker.allow(), Permit::Probe);
+        breaker.record(&failure());
+
+        assert_eq!(breaker.allow(), Permit::Denied);
+    }
+
+    #[test]
+    fn dropped_probe_lets_the_next_one_through() {
+        let breaker = breaker(1, Duration::from_millis(10));
+        breaker.record(&failure());
+        std::thread::sleep(Duration::from_millis(20));
+
+        let permit = breaker.allow();
+        assert_eq!(permit, Permit::Probe);
+        drop(ProbeGuard { breaker: &breaker, armed: true });
+
+        assert_eq!(breaker.allow(), Permit::Probe);
+    }
+
+    #[test]
+    fn disabled_with_zero_threshold() {
+        let breaker = breaker(0, Duration::from_secs(60));
+        for _ in 0..10 {
+            breaker.record(&failure());
+        }
+
+        assert_eq!(breaker.allow(), Permit::Query);
+    }
+
+    #[actix_rt::test]
+    async fn slow_query_is_logged_with_the_request_id() {
+        use tracing::Instrument;
+
+        let (logs, _capture) = crate::util::logging::capture_logs();
+
+        // Never connects, the queries below don't touch the pool
+        let database = Database {
+            pool: PgPoolOptions::new().connect_lazy("postgres://localhost/kawaii").unwrap(),
+            breaker: breaker(0, Duration::from_secs(60)),
+            slow_query: Some(Duration::from_millis(5))
+        };
+
+        let fast = database.guarded("fast_query", async { Ok(()) });
+        let slow = database.guarded("get_user_by_id", async {
+            actix_web::rt::time::delay_for(Duration::from_millis(10)).await;
+            Ok(())
+        });
+        async { fast.await.and(slow.await) }
+            .instrument(tracing::info_span!("request", request_id = %"8c3f0a"))
+            .await
+            .unwrap();
+
+        let logs = logs.text();
+        let warning = logs.lines().find(|line| line.contains("slow database query")).unwrap();
+        assert!(warning.contains("request_id=8c3f0a"));
+        assert!(warning.contains("get_user_by_id"));
+        assert!(!logs.contains("fast_query"));
+    }
 }
\ No newline at end of file
@@ -1,46 +1,74 @@
 use actix_web::*;
 use storage::Storage;
-use hmac::{Hmac, NewMac};
-use rand::Rng;
+use util::{auth::JwtKeys, cors::Cors};
 
 extern crate dotenv;
 extern crate argon2;
 
 mod database;
+mod mailer;
 mod models;
 mod config;
 mod state;
 mod routes;
 mod storage;
 mod util;
+mod webhook;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let config = config::Config::new();
+    util::logging::init(&config);
+    let port = config.port;
 
-    let database = database::Database::new(16, &config.database_url).await;
-    let storage = Storage::new(&config.s3_bucket, &config.s3_access_key, &config.s3_secret_key, config.s3_region);
+    let database = database::Database::new(16, &config).await;
+    let storage = Storage::new(&config.s3_bucket, &config.s3_access_key, &config.s3_secret_key, config.s3_region.clone());
+
+    let jwt_keys = JwtKeys::from_config(&config);
+    let webhooks = webhook::Webhooks::new(&config.webhook_urls, &config.webhook_secret);
+    let login_captcha = util::captcha::LoginCaptcha::from_config(&config);
+    let login_ips = util::login_ips::LoginIps::from_config(&config);
+    let passkeys = util::webauthn::Passkeys::from_config(&config);
+    let rate_limiter = util::rate_limit::from_config(&config);
+    let outbox = mailer::Outbox::from_config(&config);
 
     let api_state = web::Data::new(state::State {
+        config,
         database: database,
         storage: storage,
-        jwt_key: Hmac::new_varkey(&rand::thread_rng().gen::<[u8; 32]>()).expect("Could not generate JWT key")
+        jwt_keys,
+        webhooks,
+        login_captcha,
+        login_ips,
+        passkeys,
+        rate_limiter,
+        outbox
     });
 
     HttpServer::new(move || {
         App::new() 
+            .wrap(Cors::new(&api_state.config, routes::route_methods))
+            .wrap(util::headers::security_headers(&api_state.config))
+            .wrap(middleware::Condition::new(api_state.config.force_https, util::https::ForceHttps::new(&api_state.config.trusted_proxies)))
+            .wrap_fn(util::logging::log_request)
             .app_data(api_state.clone())
             .service(
                 web::scope("/api/v1/")
+                    .wrap_fn(util::outage::unavailable_during_outage)
+                    .wrap_fn(util::rate_limit::limit_requests)
                     .service(routes::user::get_routes())
                     .service(routes::auth::get_routes())
+                    .service(routes::admin::get_routes())
             )
+            .service(routes::well_known::get_routes())
+            // Same error shape as the API for unknown paths and wrong methods
+            .default_service(web::route().to(routes::fallback))
             // Error handler when json body deserialization failed
             .app_data(web::JsonConfig::default().error_handler(|_, _| {
                 Error::from(models::MessageResponse::bad_request())
             }))
     })
-    .bind(("0.0.0.0", config.port))?
+    .bind(("0.0.0.0", port))?
     .run()
     .await
 }
\ No newline at end of file
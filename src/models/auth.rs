@@ -1,7 +1,128 @@
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use webauthn_rs::proto::{PublicKeyCredential, RequestChallengeResponse};
 
-#[derive(Deserialize)]
+/// Login form. The email is trimmed and lowercased, a password that isn't a string
+/// fails validation on the password field, and lengths are capped before anything gets hashed
+#[derive(Deserialize, Validate)]
 pub struct BasicAuthForm {
+    #[serde(deserialize_with = "crate::util::form::trimmed_lowercase")]
+    #[validate(email, length(max = 254))]
     pub email: String,
-    pub password: String
+
+    #[serde(default, deserialize_with = "crate::util::form::string_only")]
+    #[validate(required, length(min = 1, max = 128))]
+    pub password: Option<String>,
+
+    /// Where to send a browser after logging in
+    pub next: Option<String>,
+
+    /// Solved captcha, needed after too many failed logins
+    pub captcha_token: Option<String>,
+
+    /// Replacement for an expired password when rotation is enforced
+    #[validate(length(max = 128))]
+    pub new_password: Option<String>,
+
+    /// Code mailed to an account flagged for logging in from too many IPs
+    #[validate(length(max = 64))]
+    pub verification_code: Option<String>
+}
+
+impl BasicAuthForm {
+    /// The submitted password, empty only when the form wasn't validated
+    pub fn password(&self) -> &str {
+        self.password.as_deref().unwrap_or_default()
+    }
+}
+
+/// Response of a successful login
+#[derive(Serialize)]
+pub struct LoginResponse {
+    pub message: &'static str,
+    /// The password is past its maximum age and should be changed
+    pub password_expired: bool,
+    /// The password reaches its maximum age within a week
+    pub password_expires_soon: bool
+}
+
+/// Public key in JWK format (RFC 7517)
+#[derive(Serialize)]
+pub struct Jwk {
+    pub kty: &'static str,
+    #[serde(rename = "use")]
+    pub key_use: &'static str,
+    pub alg: &'static str,
+    pub kid: String,
+
+    // RSA public key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+
+    // EC public key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+/// Set of keys auth tokens can be verified with
+#[derive(Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>
+}
+
+/// What external services need to verify auth tokens
+#[derive(Serialize)]
+pub struct AuthConfig {
+    pub issuer: String,
+    pub algorithm: &'static str
+}
+
+/// Start logging in with a passkey
+#[derive(Deserialize)]
+pub struct PasskeyLoginForm {
+    pub email: String
+}
+
+/// Challenge the authenticator has to sign, `login_id` is sent back with the answer
+#[derive(Serialize)]
+pub struct PasskeyChallenge {
+    pub login_id: String,
+    pub challenge: RequestChallengeResponse
+}
+
+/// Signed challenge from the authenticator
+#[derive(Deserialize)]
+pub struct PasskeyLoginFinishForm {
+    pub login_id: String,
+    pub credential: PublicKeyCredential
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ValidationResponse;
+    use actix_web::http::StatusCode;
+
+    #[test]
+    fn login_email_is_trimmed_and_lowercased() {
+        let form: BasicAuthForm = serde_json::from_value(serde_json::json!({ "email": "  User@X.com ", "password": "hunter22" })).unwrap();
+
+        assert_eq!(form.email, "user@x.com");
+        assert!(form.validate().is_ok());
+    }
+
+    #[test]
+    fn password_that_isnt_a_string_fails_on_the_password_field() {
+        let form: BasicAuthForm = serde_json::from_value(serde_json::json!({ "email": "user@x.com", "password": 123456 })).unwrap();
+        let response = ValidationResponse::from(form.validate().unwrap_err());
+
+        assert_eq!(response.http_response().status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(serde_json::to_value(&response).unwrap()["errors"], serde_json::json!([{ "field": "password", "code": "required" }]));
+    }
 }
\ No newline at end of file
@@ -7,6 +7,7 @@ pub use self::{user::*, auth::*, token::*};
 use actix_web::{Error, HttpRequest, HttpResponse, Responder, http::StatusCode};
 use futures::future::{Ready, ok};
 use serde::Serialize;
+use validator::ValidationErrors;
 
 /// Standard message response
 #[derive(Serialize)]
@@ -15,27 +16,66 @@ pub struct MessageResponse {
     code: StatusCode,
 
     message: String,
+
+    /// Machine readable error identifier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<String>,
 }
 
 impl MessageResponse {
     /// Create new message response
     pub fn new(code: StatusCode, message: &str) -> Self {
         MessageResponse {
-            code: code,
+            code,
             message: message.to_string(),
+            error_code: None,
         }
     }
+    /// Create new error response with an error code
+    fn error(code: StatusCode, message: &str, error_code: &str) -> Self {
+        MessageResponse::new(code, message).with_error_code(error_code)
+    }
     /// New internal server error response
     pub fn internal_server_error() -> Self {
-        MessageResponse::new(StatusCode::INTERNAL_SERVER_ERROR, "There was a problem processing your request")
+        MessageResponse::error(StatusCode::INTERNAL_SERVER_ERROR, "There was a problem processing your request", "INTERNAL_ERROR")
     }
     /// Create new unauthorized error response
     pub fn unauthorized_error() -> Self {
-        MessageResponse::new(StatusCode::UNAUTHORIZED, "You are not authorized to make this request")
+        MessageResponse::error(StatusCode::UNAUTHORIZED, "You are not authorized to make this request", "UNAUTHORIZED")
     }
     /// Create new bad request error response
     pub fn bad_request() -> Self {
-        MessageResponse::new(StatusCode::BAD_REQUEST, "You sent an invalid request")
+        MessageResponse::error(StatusCode::BAD_REQUEST, "You sent an invalid request", "BAD_REQUEST")
+    }
+    /// Create new forbidden error response
+    pub fn forbidden() -> Self {
+        MessageResponse::error(StatusCode::FORBIDDEN, "You are not allowed to do this", "FORBIDDEN")
+    }
+    /// Create new not found error response
+    pub fn not_found() -> Self {
+        MessageResponse::error(StatusCode::NOT_FOUND, "The requested resource does not exist", "NOT_FOUND")
+    }
+    /// Create new conflict error response
+    pub fn conflict() -> Self {
+        MessageResponse::error(StatusCode::CONFLICT, "The resource already exists", "CONFLICT")
+    }
+    /// Create new rate limit error response
+    pub fn too_many_requests() -> Self {
+        MessageResponse::error(StatusCode::TOO_MANY_REQUESTS, "You are sending too many requests", "TOO_MANY_REQUESTS")
+    }
+    /// Create new service unavailable error response
+    pub fn service_unavailable() -> Self {
+        MessageResponse::error(StatusCode::SERVICE_UNAVAILABLE, "The service is temporarily unavailable, try again later", "SERVICE_UNAVAILABLE")
+    }
+    /// Replace the default message
+    pub fn with_message(mut self, message: &str) -> Self {
+        self.message = message.to_string();
+        self
+    }
+    /// Replace the default error code
+    pub fn with_error_code(mut self, error_code: &str) -> Self {
+        self.error_code = Some(error_code.to_string());
+        self
     }
     /// Explicit convert to actix HttpResponse type
     pub fn http_response(&self) -> HttpResponse {
@@ -67,4 +107,189 @@ impl Responder for MessageResponse {
     fn respond_to(self, _: &HttpRequest) -> Self::Future {
         ok(HttpResponse::from(self))
     }
+}
+
+/// One page of a list endpoint
+#[derive(Serialize)]
+pub struct Page<T: Serialize> {
+    pub items: Vec<T>,
+    pub page: i64,
+    pub limit: i64,
+    pub total: i64,
+}
+
+impl<T: Serialize> Page<T> {
+    /// Respond with the page, with `Link` headers (RFC 8288) to the first, last and neighbouring pages
+    pub fn http_response(&self, req: &HttpRequest) -> HttpResponse {
+        let last = ((self.total + self.limit - 1) / self.limit).max(1);
+
+        let mut links = vec![(1, "first")];
+        if self.page > 1 {
+            links.push((self.page - 1, "prev"));
+        }
+        if self.page < last {
+            links.push((self.page + 1, "next"));
+        }
+        links.push((last, "last"));
+
+        // Keep the other query parameters, so links stay within the same listing
+        let query: Vec<(String, String)> = serde_urlencoded::from_str::<Vec<(String, String)>>(req.query_string())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(name, _)| name != "page")
+            .collect();
+
+        let link = links.into_iter()
+            .map(|(page, rel)| {
+                let mut params = query.clone();
+                params.push(("page".to_string(), page.to_string()));
+                let query = serde_urlencoded::to_string(params).unwrap_or_default();
+                format!("<{}?{}>; rel=\"{}\"", req.path(), query, rel)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        HttpResponse::Ok()
+            .header(actix_web::http::header::LINK, link)
+            .json(self)
+    }
+}
+
+/// A single invalid field of a submitted form
+#[derive(Serialize)]
+pub struct FieldError {
+    field: String,
+    code: String,
+}
+
+/// Response for a form that was well formed but failed validation
+#[derive(Serialize)]
+pub struct ValidationResponse {
+    message: String,
+    errors: Vec<FieldError>,
+}
+
+impl ValidationResponse {
+    /// Explicit convert to actix HttpResponse type
+    pub fn http_response(&self) -> HttpResponse {
+        HttpResponse::build(StatusCode::UNPROCESSABLE_ENTITY)
+            .json(self)
+    }
+}
+
+/// Collect the field errors reported by the validator
+impl From<ValidationErrors> for ValidationResponse {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut field_errors: Vec<FieldError> = errors.field_errors()
+            .into_iter()
+            .flat_map(|(field, errors)| {
+                errors.iter().map(move |error| FieldError {
+                    field: field.to_string(),
+                    code: error.code.to_string(),
+                })
+            })
+            .collect();
+
+        // Keep the output stable, the validator stores fields in a hash map
+        field_errors.sort_by(|a, b| a.field.cmp(&b.field));
+
+        ValidationResponse {
+            message: "Some fields are invalid".to_string(),
+            errors: field_errors,
+        }
+    }
+}
+
+/// Implicit From convert to actix HttpResponse type
+impl From<ValidationResponse> for HttpResponse {
+    fn from(response: ValidationResponse) -> Self {
+        response.http_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::Validate;
+
+    #[test]
+    fn error_helpers_set_status_and_code() {
+        let cases = [
+            (MessageResponse::bad_request(), StatusCode::BAD_REQUEST, "BAD_REQUEST"),
+            (MessageResponse::forbidden(), StatusCode::FORBIDDEN, "FORBIDDEN"),
+            (MessageResponse::not_found(), StatusCode::NOT_FOUND, "NOT_FOUND"),
+            (MessageResponse::conflict(), StatusCode::CONFLICT, "CONFLICT"),
+            (MessageResponse::too_many_requests(), StatusCode::TOO_MANY_REQUESTS, "TOO_MANY_REQUESTS"),
+            (MessageResponse::service_unavailable(), StatusCode::SERVICE_UNAVAILABLE, "SERVICE_UNAVAILABLE"),
+            (MessageResponse::unauthorized_error(), StatusCode::UNAUTHORIZED, "UNAUTHORIZED"),
+            (MessageResponse::internal_server_error(), StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR"),
+        ];
+
+        for (response, status, error_code) in cases {
+            assert_eq!(response.http_response().status(), status);
+            assert_eq!(response.error_code.as_deref(), Some(error_code));
+            assert!(!response.message.is_empty());
+        }
+    }
+
+    #[test]
+    fn message_and_code_can_be_replaced() {
+        let response = MessageResponse::bad_request().with_message("Nope").with_error_code("NOPE");
+        let body = serde_json::to_value(&response).unwrap();
+
+        assert_eq!(response.http_response().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body, serde_json::json!({ "message": "Nope", "error_code": "NOPE" }));
+    }
+
+    #[test]
+    fn plain_messages_have_no_error_code() {
+        let body = serde_json::to_value(MessageResponse::new(StatusCode::OK, "Done")).unwrap();
+
+        assert_eq!(body, serde_json::json!({ "message": "Done" }));
+    }
+
+    #[test]
+    fn empty_email_is_a_field_error() {
+        let login = BasicAuthForm { email: String::new(), password: Some("hunter22".into()), next: None, captcha_token: None, new_password: None, verification_code: None };
+        let registration = UserCreateForm { username: "kawaii".into(), email: String::new(), password: "hunter22".into() };
+
+        for errors in [login.validate().unwrap_err(), registration.validate().unwrap_err()] {
+            let response = ValidationResponse::from(errors);
+
+            assert_eq!(response.http_response().status(), StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(serde_json::to_value(&response).unwrap()["errors"], serde_json::json!([{ "field": "email", "code": "email" }]));
+        }
+    }
+
+    #[test]
+    fn valid_forms_pass_validation() {
+        let login = BasicAuthForm { email: "kawaii@example.com".into(), password: Some("hunter22".into()), next: None, captcha_token: None, new_password: None, verification_code: None };
+        let registration = UserCreateForm { username: "kawaii".into(), email: "kawaii@example.com".into(), password: "hunter22".into() };
+
+        assert!(login.validate().is_ok());
+        assert!(registration.validate().is_ok());
+    }
+
+    fn links(uri: &str, page: i64) -> String {
+        let req = actix_web::test::TestRequest::with_uri(uri).to_http_request();
+        let response = Page { items: Vec::<i32>::new(), page, limit: 10, total: 30 }.http_response(&req);
+        response.headers().get(actix_web::http::header::LINK).unwrap().to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn first_page_links_to_next_only() {
+        assert_eq!(links("/tokens?page=1", 1), "</tokens?page=1>; rel=\"first\", </tokens?page=2>; rel=\"next\", </tokens?page=3>; rel=\"last\"");
+    }
+
+    #[test]
+    fn last_page_links_to_prev_only() {
+        assert_eq!(links("/tokens?page=3", 3), "</tokens?page=1>; rel=\"first\", </tokens?page=2>; rel=\"prev\", </tokens?page=3>; rel=\"last\"");
+    }
+
+    #[test]
+    fn links_keep_other_query_parameters() {
+        let links = links("/tokens?limit=10&page=2&name=ci", 2);
+        assert!(links.contains("</tokens?limit=10&name=ci&page=1>; rel=\"prev\""), "{}", links);
+        assert!(links.contains("</tokens?limit=10&name=ci&page=3>; rel=\"next\""), "{}", links);
+    }
 }
\ No newline at end of file
@@ -1,9 +1,15 @@
 use serde::{Serialize, Deserialize};
+use validator::Validate;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct UserCreateForm {
+    #[validate(length(min = 4, max = 15))]
     pub username: String,
+
+    #[validate(email)]
     pub email: String,
+
+    #[validate(length(min = 6, max = 128))]
     pub password: String
 }
 
@@ -14,25 +20,112 @@ pub struct PasswordChangeForm {
     pub new_password: String
 }
 
+/// New email for an account, the current password confirms it's the owner asking
+#[derive(Deserialize, Validate)]
+pub struct EmailChangeForm {
+    #[serde(deserialize_with = "crate::util::form::trimmed_lowercase")]
+    #[validate(email, length(max = 254))]
+    pub email: String,
+
+    pub password: String
+}
+
+/// Token from an email change confirmation mail
+#[derive(Deserialize)]
+pub struct EmailConfirmQuery {
+    pub token: String
+}
+
+/// Password confirmation for deleting an account
+#[derive(Deserialize)]
+pub struct AccountDeleteForm {
+    pub password: String
+}
+
+/// Account migrated from another host, keeping its existing password hash
+#[derive(Deserialize, Validate)]
+pub struct UserImportRecord {
+    #[validate(email)]
+    pub email: String,
+
+    #[validate(length(min = 4, max = 15))]
+    pub username: String,
+
+    pub password_hash: String,
+
+    #[serde(default)]
+    pub role: UserRole
+}
+
+/// Outcome of importing a single record
+#[derive(Serialize)]
+pub struct UserImportResult {
+    pub email: String,
+    pub status: u16,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>
+}
+
 /// User role in database
-#[derive(Serialize, Deserialize, sqlx::Type, PartialEq, PartialOrd)]
+#[derive(Serialize, Deserialize, sqlx::Type, PartialEq, PartialOrd, Default)]
 #[sqlx(rename = "role", rename_all = "lowercase")]
 #[serde(rename_all(serialize  = "lowercase", deserialize  = "PascalCase"))]
 pub enum UserRole {
+    #[default]
     User,
     Admin
 }
 
+/// User as listed to admins, with the id other admin routes take
+#[derive(Serialize)]
+pub struct UserListEntry {
+    pub id: i32,
+    pub username: String,
+    pub email: String,
+    pub verified: bool,
+    pub role: UserRole
+}
+
+impl From<UserData> for UserListEntry {
+    fn from(user: UserData) -> Self {
+        UserListEntry {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            verified: user.verified,
+            role: user.role
+        }
+    }
+}
+
+/// Everything stored about an account, handed to its owner
+#[derive(Serialize)]
+pub struct AccountExport {
+    pub exported_at: i64,
+    pub tenant: String,
+    pub profile: UserListEntry
+}
+
 #[derive(Serialize)]
 pub struct UserData {
     #[serde(skip_serializing)]
     pub id: i32,
 
+    #[serde(skip_serializing)]
+    pub tenant: String,
+
     #[serde(skip_serializing)]
     pub password: String,
 
     pub username: String,
     pub email: String,
     pub verified: bool,
-    pub role: UserRole
+    pub role: UserRole,
+
+    #[serde(skip_serializing)]
+    pub token_version: i32,
+
+    #[serde(skip_serializing)]
+    pub password_changed_at: chrono::NaiveDateTime
 }
\ No newline at end of file
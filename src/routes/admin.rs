@@ -0,0 +1,193 @@
+use actix_web::*;
+use actix_web::http::{Method, StatusCode};
+use chrono::Utc;
+
+use validator::Validate;
+
+use crate::{config::Config, database::DatabaseError, models::*, state::State, util::{auth::{self, *}, cookies::auth_cookie, pagination::Pagination, user::{is_reserved_username, is_supported_hash}}};
+use super::Route;
+
+/// Most records accepted by a single import request
+const MAX_IMPORT_BATCH: usize = 100;
+
+pub const ROUTES: &[Route] = &[
+    Route { name: "users", methods: &[Method::GET], mount: |scope| scope.service(users) },
+    Route { name: "impersonate", methods: &[Method::POST], mount: |scope| scope.service(impersonate) },
+    Route { name: "import_users", methods: &[Method::POST], mount: |scope| scope.service(import_users) },
+];
+
+pub fn get_routes() -> Scope {
+    super::mount(web::scope("/admin/"), ROUTES)
+}
+
+/// List the users of the admin's tenant
+#[get("users")]
+async fn users(req: HttpRequest, state: web::Data<State>, admin: auth::middleware::Admin, pagination: Pagination) -> impl Responder {
+    let users = match state.database.get_users(&admin.0.tenant, pagination.offset(), pagination.limit).await {
+        Ok(users) => users,
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    };
+
+    let total = match state.database.get_user_count(&admin.0.tenant).await {
+        Ok(total) => total,
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    };
+
+    Page {
+        items: users.into_iter().map(UserListEntry::from).collect::<Vec<_>>(),
+        page: pagination.page,
+        limit: pagination.limit,
+        total
+    }.http_response(&req)
+}
+
+/// Log in as another user of the same tenant with a short lived token
+#[post("users/{id}/impersonate")]
+async fn impersonate(state: web::Data<State>, admin: auth::middleware::Admin, web::Path(id): web::Path<u32>) -> impl Responder {
+    // Impersonation sessions can't be used to start new ones
+    if let Err(err) = admin.1.not_impersonating("You can't impersonate while impersonating") {
+        return err.http_response();
+    }
+
+    let target = match state.database.get_user_by_id(&admin.0.tenant, id).await {
+        Ok(target) => target,
+        Err(DatabaseError::NotFound) => return MessageResponse::not_found().with_message("User not found").http_response(),
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    };
+
+    let (jwt, expire_time) = match impersonation_token(&state.config, &state.jwt_keys, admin.0.id, &target, Utc::now().timestamp()) {
+        Ok(token) => token,
+        Err(err) => return err.http_response()
+    };
+
+    // Every impersonation has to be on record before the token is handed out
+    if state.database.insert_audit_log(&admin.0.tenant, admin.1.actor(admin.0.id), Some(target.id), "user.impersonate").await.is_err() {
+        return MessageResponse::internal_server_error().http_response();
+    }
+
+    HttpResponse::Ok()
+        .cookie(auth_cookie(&state.config, jwt, expire_time))
+        .json(MessageResponse::new(StatusCode::OK, "You are now logged in as this user"))
+}
+
+/// Token for `admin_id` acting as `target` and its expiry. Admins can't be impersonated,
+/// that would let one admin act with another's identity
+fn impersonation_token(config: &Config, keys: &JwtKeys, admin_id: i32, target: &UserData, now: i64) -> Result<(String, i64), MessageResponse> {
+    if target.role == UserRole::Admin {
+        return Err(MessageResponse::forbidden().with_message("Admins can't be impersonated"));
+    }
+
+    let expire_time = now + config.impersonation_minutes * 60;
+
+    match create_jwt_string(target.id, &target.tenant, target.token_version, Some(admin_id), &config.jwt_issuer, expire_time, keys) {
+        Ok(jwt) => Ok((jwt, expire_time)),
+        Err(_) => Err(MessageResponse::internal_server_error())
+    }
+}
+
+/// Import accounts from another host, keeping their password hashes
+#[post("users/import")]
+async fn import_users(state: web::Data<State>, admin: auth::middleware::Admin, records: web::Json<Vec<UserImportRecord>>) -> impl Responder {
+    if records.is_empty() || records.len() > MAX_IMPORT_BATCH {
+        return MessageResponse::bad_request()
+            .with_message(&format!("An import must contain between 1 and {} records", MAX_IMPORT_BATCH))
+            .with_error_code("INVALID_BATCH_SIZE")
+            .http_response();
+    }
+
+    // Reject malformed records up front, only the rest goes to the database
+    let checked: Vec<Option<&str>> = records.iter()
+        .map(|record| {
+            if record.validate().is_err() {
+                Some("INVALID_RECORD")
+            } else if is_reserved_username(&record.username, &state.config.reserved_usernames) {
+                Some("USERNAME_RESERVED")
+            } else if !is_supported_hash(&record.password_hash) {
+                Some("INVALID_HASH")
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let valid: Vec<&UserImportRecord> = records.iter()
+        .zip(&checked)
+        .filter(|(_, error)| error.is_none())
+        .map(|(record, _)| record)
+        .collect();
+
+    let mut inserted = match state.database.import_users(&admin.0.tenant, &valid).await {
+        Ok(inserted) => inserted.into_iter(),
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    };
+
+    let mut results = Vec::with_capacity(records.len());
+    for (record, error) in records.iter().zip(checked) {
+        let (status, error_code) = match error {
+            Some(error) => (StatusCode::UNPROCESSABLE_ENTITY, Some(error)),
+            None if inserted.next() == Some(true) => (StatusCode::CREATED, None),
+            // Either unique index could have stopped it, tell them which one so the record can be fixed
+            None => match state.database.get_user_by_username(&admin.0.tenant, &record.username).await {
+                Ok(_) => (StatusCode::CONFLICT, Some("USERNAME_TAKEN")),
+                Err(DatabaseError::NotFound) => (StatusCode::CONFLICT, Some("EMAIL_TAKEN")),
+                Err(_) => return MessageResponse::internal_server_error().http_response()
+            }
+        };
+
+        results.push(UserImportResult {
+            email: record.email.clone(),
+            status: status.as_u16(),
+            error_code: error_code.map(str::to_string)
+        });
+    }
+
+    if state.database.insert_audit_log(&admin.0.tenant, admin.1.actor(admin.0.id), None, "user.import").await.is_err() {
+        return MessageResponse::internal_server_error().http_response();
+    }
+
+    HttpResponse::build(StatusCode::MULTI_STATUS).json(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i32, role: UserRole) -> UserData {
+        UserData {
+            id,
+            tenant: "main".into(),
+            password: String::new(),
+            username: format!("user{}", id),
+            email: format!("user{}@example.com", id),
+            verified: true,
+            role,
+            token_version: 3,
+            password_changed_at: Utc::now().naive_utc()
+        }
+    }
+
+    #[test]
+    fn impersonation_token_acts_as_the_target() {
+        let config = Config::for_test();
+        let keys = JwtKeys::from_config(&config);
+
+        let (jwt, expire_time) = impersonation_token(&config, &keys, 1, &user(7, UserRole::User), 1_700_000_000).ok().unwrap();
+        let claims = keys.verify(&jwt).unwrap();
+
+        assert_eq!(claims.registered.subject.as_deref(), Some("7"));
+        assert_eq!(claims.tenant, "main");
+        assert_eq!(claims.version, 3);
+        assert_eq!(claims.impersonator, Some(1));
+        assert_eq!(expire_time, 1_700_000_000 + config.impersonation_minutes * 60);
+    }
+
+    #[test]
+    fn admins_cant_be_impersonated() {
+        let config = Config::for_test();
+        let keys = JwtKeys::from_config(&config);
+
+        let refused = impersonation_token(&config, &keys, 1, &user(2, UserRole::Admin), 1_700_000_000).err().unwrap();
+
+        assert_eq!(refused.http_response().status(), StatusCode::FORBIDDEN);
+    }
+}
\ No newline at end of file
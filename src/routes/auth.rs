@@ -1,53 +1,604 @@
 use actix_web::*;
-use actix_web::http::StatusCode;
+use actix_web::http::{Method, StatusCode};
 use models::*;
-use time::OffsetDateTime;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rand::Rng;
+use std::net::IpAddr;
+use sha2::{Digest, Sha256};
+use validator::Validate;
 
-use crate::{models::{self, auth::BasicAuthForm}, util::auth::*, state::State};
+use crate::{database::DatabaseError, mailer::Outbox, models::{self, auth::BasicAuthForm}, util::{auth::{self, *}, captcha::LoginCaptcha, client_ip::client_ip, cookies::auth_cookie, form::JsonOrForm, tenant::Tenant, user::{new_password, verify_decoy, verify_password}}, state::State};
+use super::Route;
+
+/// Days before expiry a password starts being reported as expiring soon
+const PASSWORD_EXPIRY_WARNING_DAYS: i64 = 7;
+
+/// Hours the link confirming a new email stays valid
+const EMAIL_CHANGE_TOKEN_HOURS: i64 = 24;
+
+pub const ROUTES: &[Route] = &[
+    Route { name: "basic", methods: &[Method::POST], mount: |scope| scope.service(basic) },
+    Route { name: "config", methods: &[Method::GET], mount: |scope| scope.service(config) },
+    Route { name: "export", methods: &[Method::GET], mount: |scope| scope.service(export) },
+    Route { name: "delete_account", methods: &[Method::DELETE], mount: |scope| scope.service(delete_account) },
+    Route { name: "change_email", methods: &[Method::POST], mount: |scope| scope.service(change_email) },
+    Route { name: "confirm_email", methods: &[Method::GET], mount: |scope| scope.service(confirm_email) },
+];
 
 pub fn get_routes() -> Scope {
-    web::scope("/auth/")
-        .service(basic)
+    super::mount(web::scope("/auth/"), ROUTES)
+        .service(super::webauthn::get_routes())
 }
 
-/// Login with email and password
+/// Login with email and password, sent as JSON or a plain HTML form
 #[post("basic")]
-async fn basic(state: web::Data<State>, data: web::Json<BasicAuthForm>) -> impl Responder {
+async fn basic(req: HttpRequest, state: web::Data<State>, tenant: Tenant, JsonOrForm(data): JsonOrForm<BasicAuthForm>) -> impl Responder {
+    // Check the form before touching the database, so this never depends on which accounts exist
+    if let Err(errors) = data.validate() {
+        return ValidationResponse::from(errors).http_response();
+    }
+
+    let ip = client_ip(req.head(), &state.config);
+
+    // Only count failures when captchas are enabled and the client address is known
+    let captcha = match (&state.login_captcha, ip) {
+        (Some(captcha), Some(ip)) => Some((captcha, ip)),
+        _ => None
+    };
+
+    if let Some((captcha, ip)) = captcha {
+        if captcha.required(ip) {
+            let solved = match &data.captcha_token {
+                Some(token) => captcha.verify(token, ip).await,
+                None => false
+            };
+
+            if !solved {
+                return MessageResponse::bad_request().with_message("Please solve the captcha to log in").with_error_code("CAPTCHA_REQUIRED").http_response();
+            }
+        }
+    }
+
     // Get user data from database
-    let user_data = match state.database.get_user_by_email(&data.email).await {
+    let user_data = match state.database.get_user_by_email(&tenant.0, &data.email).await {
         Ok(user_data) => user_data,
-        Err(_) => return MessageResponse::new(StatusCode::BAD_REQUEST, "Invalid credentials provided!").http_response()
+        Err(DatabaseError::NotFound) => {
+            verify_decoy(data.password());
+            tracing::info!(tenant = %tenant.0, "login failed, unknown email");
+            if let Some((captcha, ip)) = captcha {
+                captcha.record_failure(ip);
+            }
+            return MessageResponse::bad_request().with_message("Invalid credentials provided!").with_error_code("INVALID_CREDENTIALS").http_response();
+        },
+        Err(DatabaseError::Backend(_) | DatabaseError::Unavailable) => return match break_glass(&state, &tenant.0, &data, captcha) {
+            Some(response) => response,
+            None => MessageResponse::internal_server_error().http_response()
+        },
+        Err(_) => return MessageResponse::internal_server_error().http_response()
     };
 
     // Check if password is valid to password hash
-    let matches = match argon2::verify_encoded(&user_data.password, data.password.as_bytes()) {
+    let matches = match verify_password(&user_data.password, data.password()) {
         Ok(matches) => matches,
-        Err(_) => return MessageResponse::internal_server_error().http_response()
+        Err(err) => return err.http_response()
     };
 
     if !matches {
-        return MessageResponse::new(StatusCode::BAD_REQUEST, "Invalid credentials provided!").http_response();
+        tracing::info!(tenant = %tenant.0, user_id = user_data.id, "login failed, wrong password");
+        if let Some((captcha, ip)) = captcha {
+            captcha.record_failure(ip);
+        }
+        return MessageResponse::bad_request().with_message("Invalid credentials provided!").with_error_code("INVALID_CREDENTIALS").http_response();
+    }
+
+    tracing::info!(tenant = %tenant.0, user_id = user_data.id, "login succeeded");
+    if let Some((captcha, ip)) = captcha {
+        captcha.reset(ip);
+    }
+
+    if let Err(err) = check_login_ips(&state, &user_data, ip, data.verification_code.as_deref()) {
+        return err.http_response();
     }
 
     let utc: DateTime<Utc> = Utc::now();
-    let one_week = chrono::Duration::weeks(1);
-    let expire_time = (utc + one_week).timestamp();
 
-    let jwt = match create_jwt_string(user_data.id, "localhost", expire_time, &state.jwt_key) {
-        Ok(jwt) => jwt,
-        Err(_) => return MessageResponse::internal_server_error().http_response()
+    let (mut password_expired, password_expires_soon) = password_status(user_data.password_changed_at, utc.naive_utc(), state.config.password_max_age_days);
+
+    // Under enforced rotation an expired password only logs in together with its replacement
+    if password_expired && state.config.enforce_password_rotation {
+        let replacement = match password_replacement(&data) {
+            Ok(replacement) => replacement,
+            Err(err) => return err.http_response()
+        };
+
+        let hash = match new_password(replacement) {
+            Ok(hash) => hash,
+            Err(err) => return err.http_response()
+        };
+
+        if state.database.change_password(&tenant.0, user_data.id as u32, &hash).await.is_err() {
+            return MessageResponse::internal_server_error().http_response();
+        }
+
+        password_expired = false;
+    }
+
+    let cookie = match session_cookie(&state, &user_data).await {
+        Ok(cookie) => cookie,
+        Err(err) => return err.http_response()
     };
 
+    // Browser flows can ask to be sent back to an allowed page
+    if let Some(next) = login_redirect(data.next.as_deref(), &state.config.login_redirect_allowlist) {
+        return HttpResponse::SeeOther()
+            .cookie(cookie)
+            .header(http::header::LOCATION, next)
+            .finish();
+    }
+
     // Set JWT token as cookie
     HttpResponse::Ok()
-        .cookie(
-            http::Cookie::build("auth-token", jwt)
-            .secure(false)
-            .http_only(true)
-            .path("/")
-            .expires(OffsetDateTime::from_unix_timestamp(expire_time))
-            .finish()
-        )
-        .json(MessageResponse::new(StatusCode::OK, "You have logged in"))
+        .cookie(cookie)
+        .json(LoginResponse {
+            message: "You have logged in",
+            password_expired,
+            password_expires_soon
+        })
+}
+
+/// Flag accounts logging in from too many IPs, and hold their logins back until the owner
+/// enters the code mailed to them when verification is required
+fn check_login_ips(state: &State, user: &UserData, ip: Option<IpAddr>, code: Option<&str>) -> Result<(), MessageResponse> {
+    let (login_ips, ip) = match (&state.login_ips, ip) {
+        (Some(login_ips), Some(ip)) => (login_ips, ip),
+        _ => return Ok(())
+    };
+
+    if !login_ips.record(&user.tenant, user.id, ip) {
+        return Ok(());
+    }
+    tracing::warn!(tenant = %user.tenant, user_id = user.id, "login from too many ips");
+
+    if !state.config.login_ip_require_verification {
+        return Ok(());
+    }
+
+    if code.is_some_and(|code| login_ips.verify(&user.tenant, user.id, code)) {
+        tracing::info!(tenant = %user.tenant, user_id = user.id, "login verified by email");
+        return Ok(());
+    }
+
+    // Sent once per code, so retrying the login doesn't flood the inbox
+    if let Some(code) = login_ips.issue_code(&user.tenant, user.id) {
+        send_verification_code(&state.outbox, &user.email, &code);
+    }
+
+    Err(MessageResponse::forbidden().with_message("Please enter the verification code sent to your email").with_error_code("VERIFICATION_REQUIRED"))
+}
+
+fn send_verification_code(outbox: &Outbox, email: &str, code: &str) {
+    let body = format!("Hi,\n\nyour kawaii account was logged into from many places at once. If that was you, log in again with the verification code {}. If not, change your password.", code);
+    outbox.send(email, "Verify your kawaii login", &body);
+}
+
+/// Whether a password changed at `changed_at` is expired, or expires within the warning period
+fn password_status(changed_at: NaiveDateTime, now: NaiveDateTime, max_age_days: Option<i64>) -> (bool, bool) {
+    let days = match max_age_days {
+        Some(days) => days,
+        None => return (false, false)
+    };
+
+    let age = now - changed_at;
+    let max_age = chrono::Duration::days(days);
+    let expired = age >= max_age;
+    (expired, !expired && age >= max_age - chrono::Duration::days(PASSWORD_EXPIRY_WARNING_DAYS))
+}
+
+/// The new password sent along with an expired one
+fn password_replacement(data: &BasicAuthForm) -> Result<&str, MessageResponse> {
+    let replacement = match &data.new_password {
+        Some(replacement) => replacement,
+        None => return Err(MessageResponse::forbidden().with_message("Your password has expired, please choose a new one").with_error_code("PASSWORD_EXPIRED"))
+    };
+
+    if replacement == data.password() {
+        return Err(MessageResponse::bad_request().with_message("The new password has to be different").with_error_code("PASSWORD_REUSED"));
+    }
+
+    Ok(replacement)
+}
+
+/// Emergency admin login with the break glass credential, for when the database is down
+fn break_glass(state: &State, tenant: &str, data: &BasicAuthForm, captcha: Option<(&LoginCaptcha, IpAddr)>) -> Option<HttpResponse> {
+    let break_glass = state.config.break_glass.as_ref()?;
+    if !data.email.eq_ignore_ascii_case(&break_glass.email) {
+        return None;
+    }
+
+    // Nothing can be written to the audit log without a database, so it has to be in the logs
+    if !verify_password(&break_glass.password_hash, data.password()).unwrap_or(false) {
+        tracing::warn!(tenant, "failed break glass login");
+        if let Some((captcha, ip)) = captcha {
+            captcha.record_failure(ip);
+        }
+        return None;
+    }
+    tracing::warn!(tenant, "break glass admin session issued");
+
+    let expire_time = (Utc::now() + chrono::Duration::minutes(break_glass.minutes)).timestamp();
+    let jwt = create_break_glass_jwt(tenant, &state.config.jwt_issuer, expire_time, &state.jwt_keys).ok()?;
+
+    Some(HttpResponse::Ok()
+        .cookie(auth_cookie(&state.config, jwt, expire_time))
+        .json(MessageResponse::new(StatusCode::OK, "You have logged in with the break glass credential")))
+}
+
+/// Download the data stored about the logged in account
+#[get("me/export")]
+async fn export(state: web::Data<State>, auth: auth::middleware::User) -> impl Responder {
+    // The data belongs to the account owner, not to an admin looking around
+    if let Err(err) = auth.1.not_impersonating("You can't export the data of an impersonated user") {
+        return err.http_response();
+    }
+
+    let user = auth.0;
+
+    match state.database.has_recent_audit_log(&user.tenant, user.id, "user.export", state.config.export_cooldown_minutes).await {
+        Ok(false) => {},
+        Ok(true) => return MessageResponse::too_many_requests().with_message("You recently exported your data, try again later").http_response(),
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    }
+
+    if state.database.insert_audit_log(&user.tenant, auth.1.actor(user.id), Some(user.id), "user.export").await.is_err() {
+        return MessageResponse::internal_server_error().http_response();
+    }
+
+    let export = AccountExport {
+        exported_at: Utc::now().timestamp(),
+        tenant: user.tenant.clone(),
+        profile: UserListEntry::from(user)
+    };
+
+    HttpResponse::Ok()
+        .header(http::header::CONTENT_DISPOSITION, "attachment; filename=\"kawaii-export.json\"")
+        .json(export)
+}
+
+/// Permanently delete the logged in account
+#[delete("me")]
+async fn delete_account(state: web::Data<State>, auth: auth::middleware::User, form: web::Json<AccountDeleteForm>) -> impl Responder {
+    if let Err(err) = auth.1.not_impersonating("You can't delete an impersonated user") {
+        return err.http_response();
+    }
+
+    let user = auth.0;
+
+    // Make sure it's the owner and not someone at an unlocked computer
+    let matches = match verify_password(&user.password, &form.password) {
+        Ok(matches) => matches,
+        Err(err) => return err.http_response()
+    };
+
+    if !matches {
+        return MessageResponse::bad_request().with_message("Incorrect password entered").with_error_code("INVALID_CREDENTIALS").http_response();
+    }
+
+    match state.database.delete_user(&user.tenant, user.id, auth.1.actor(user.id)).await {
+        Ok(_) => {},
+        Err(DatabaseError::NotFound) => return MessageResponse::unauthorized_error().http_response(),
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    }
+
+    // Expire the cookie, the token itself stops working with the user gone
+    HttpResponse::Ok()
+        .cookie(auth_cookie(&state.config, String::new(), 0))
+        .json(MessageResponse::new(StatusCode::OK, "Your account has been deleted"))
+}
+
+/// Ask to change the account email. The old email keeps working until the new one is confirmed
+#[post("change-email")]
+async fn change_email(state: web::Data<State>, auth: auth::middleware::User, form: web::Json<EmailChangeForm>) -> impl Responder {
+    if let Err(err) = auth.1.not_impersonating("You can't change the email of an impersonated user") {
+        return err.http_response();
+    }
+
+    if let Err(errors) = form.validate() {
+        return ValidationResponse::from(errors).http_response();
+    }
+
+    let user = auth.0;
+
+    let matches = match verify_password(&user.password, &form.password) {
+        Ok(matches) => matches,
+        Err(err) => return err.http_response()
+    };
+
+    if !matches {
+        return MessageResponse::bad_request().with_message("Incorrect password entered").with_error_code("INVALID_CREDENTIALS").http_response();
+    }
+
+    // Checked again on confirmation, the email can be taken while the mail is on its way
+    match state.database.get_user_by_email(&user.tenant, &form.email).await {
+        Ok(_) => return email_taken().http_response(),
+        Err(DatabaseError::NotFound) => {},
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    }
+
+    let token = email_token();
+    let expires_at = (Utc::now() + chrono::Duration::hours(EMAIL_CHANGE_TOKEN_HOURS)).naive_utc();
+
+    match state.database.set_pending_email(&user.tenant, user.id, &form.email, &hash_email_token(&token), expires_at).await {
+        Ok(()) => {},
+        Err(DatabaseError::NotFound) => return MessageResponse::unauthorized_error().http_response(),
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    }
+
+    send_email_confirmation(&state.outbox, &form.email, &token);
+
+    MessageResponse::new(StatusCode::OK, "Check your new email address to confirm the change").http_response()
+}
+
+/// Switch to the new email with the token mailed to it
+#[get("confirm-email")]
+async fn confirm_email(state: web::Data<State>, tenant: Tenant, query: web::Query<EmailConfirmQuery>) -> impl Responder {
+    match state.database.confirm_pending_email(&tenant.0, &hash_email_token(&query.token)).await {
+        Ok(user_id) => {
+            tracing::info!(tenant = %tenant.0, user_id, "email changed");
+            MessageResponse::new(StatusCode::OK, "Your email has been changed")
+        },
+        Err(err) => confirm_email_error(err)
+    }
+}
+
+fn confirm_email_error(err: DatabaseError) -> MessageResponse {
+    match err {
+        DatabaseError::NotFound => MessageResponse::bad_request().with_message("The confirmation link is invalid or has expired").with_error_code("INVALID_TOKEN"),
+        DatabaseError::Conflict(_) => email_taken(),
+        _ => MessageResponse::internal_server_error()
+    }
+}
+
+fn email_taken() -> MessageResponse {
+    MessageResponse::conflict().with_message("An account with that email already exists!").with_error_code("EMAIL_TAKEN")
+}
+
+fn email_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Only the hash is stored, so a leaked database can't confirm pending emails
+fn hash_email_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Queued like the registration email, and only ever sent to the new address
+fn send_email_confirmation(outbox: &Outbox, email: &str, token: &str) {
+    let body = format!("Hi,\n\nopen /api/v1/auth/confirm-email?token={} to make this the email of your kawaii account. Until then your old email stays in use.", token);
+    outbox.send(email, "Confirm your new kawaii email", &body);
+}
+
+/// What is needed to verify auth tokens outside of this service
+#[get("config")]
+async fn config(state: web::Data<State>) -> impl Responder {
+    HttpResponse::Ok().json(AuthConfig {
+        issuer: state.config.jwt_issuer.clone(),
+        algorithm: state.jwt_keys.algorithm_name(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::{BreakGlass, Config}, mailer::CapturingMailer, util::{login_ips::LoginIps, logging::{capture_logs, log_request}}};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn break_glass_config() -> Config {
+        Config {
+            break_glass: Some(BreakGlass { email: "admin@example.com".into(), password_hash: new_password("correct horse").ok().unwrap(), minutes: 5 }),
+            ..Config::for_test()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn email_confirmation_goes_to_the_new_address() {
+        let mailer = Arc::new(CapturingMailer::default());
+        let outbox = Outbox::new(mailer.clone());
+
+        send_email_confirmation(&outbox, "new@example.com", "t0ken");
+        rt::time::delay_for(Duration::from_millis(10)).await;
+
+        let sent = mailer.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "new@example.com");
+        assert!(sent[0].2.contains("confirm-email?token=t0ken"));
+    }
+
+    fn login_form(new_password: Option<&str>) -> BasicAuthForm {
+        BasicAuthForm {
+            email: "kawaii@example.com".into(),
+            password: Some("old password".into()),
+            next: None,
+            captcha_token: None,
+            new_password: new_password.map(String::from),
+            verification_code: None
+        }
+    }
+
+    fn login_ips_state(require_verification: bool, mailer: Arc<CapturingMailer>) -> State {
+        let mut state = State::for_test(Config { login_ip_threshold: 1, login_ip_require_verification: require_verification, ..Config::for_test() });
+        state.login_ips = LoginIps::from_config(&state.config);
+        state.outbox = Outbox::new(mailer);
+        state
+    }
+
+    fn user() -> UserData {
+        UserData {
+            id: 1,
+            tenant: "default".into(),
+            password: String::new(),
+            username: "kawaii".into(),
+            email: "kawaii@example.com".into(),
+            verified: true,
+            role: UserRole::User,
+            token_version: 0,
+            password_changed_at: Utc::now().naive_utc()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn logins_from_too_many_ips_need_the_mailed_code() {
+        let mailer = Arc::new(CapturingMailer::default());
+        let state = login_ips_state(true, mailer.clone());
+        let (first, second) = (Some("203.0.113.9".parse().unwrap()), Some("198.51.100.1".parse().unwrap()));
+
+        assert!(check_login_ips(&state, &user(), first, None).is_ok());
+        let err = check_login_ips(&state, &user(), second, None).err().unwrap();
+        assert_eq!(err.http_response().status(), StatusCode::FORBIDDEN);
+        assert_eq!(serde_json::to_value(err).unwrap()["error_code"], "VERIFICATION_REQUIRED");
+        assert!(check_login_ips(&state, &user(), first, Some("wrong")).is_err());
+
+        rt::time::delay_for(Duration::from_millis(10)).await;
+        let code = {
+            let sent = mailer.sent.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].0, "kawaii@example.com");
+            sent[0].2.split("verification code ").nth(1).unwrap()[..32].to_string()
+        };
+
+        assert!(check_login_ips(&state, &user(), second, Some(&code)).is_ok());
+        assert!(check_login_ips(&state, &user(), second, None).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn flagged_logins_go_through_without_required_verification() {
+        let mailer = Arc::new(CapturingMailer::default());
+        let state = login_ips_state(false, mailer.clone());
+
+        assert!(check_login_ips(&state, &user(), Some("203.0.113.9".parse().unwrap()), None).is_ok());
+        assert!(check_login_ips(&state, &user(), Some("198.51.100.1".parse().unwrap()), None).is_ok());
+
+        rt::time::delay_for(Duration::from_millis(10)).await;
+        assert!(mailer.sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn password_status_follows_the_max_age() {
+        let now = Utc::now().naive_utc();
+        let changed = |days| now - chrono::Duration::days(days);
+
+        assert_eq!(password_status(changed(10), now, Some(90)), (false, false));
+        assert_eq!(password_status(changed(85), now, Some(90)), (false, true));
+        assert_eq!(password_status(changed(90), now, Some(90)), (true, false));
+        assert_eq!(password_status(changed(400), now, None), (false, false));
+    }
+
+    #[test]
+    fn expired_password_needs_a_different_replacement() {
+        let missing = password_replacement(&login_form(None)).err().unwrap();
+        assert_eq!(missing.http_response().status(), StatusCode::FORBIDDEN);
+        assert_eq!(serde_json::to_value(missing).unwrap()["error_code"], "PASSWORD_EXPIRED");
+
+        let reused = password_replacement(&login_form(Some("old password"))).err().unwrap();
+        assert_eq!(serde_json::to_value(reused).unwrap()["error_code"], "PASSWORD_REUSED");
+
+        assert_eq!(password_replacement(&login_form(Some("new password"))).ok(), Some("new password"));
+    }
+
+    #[test]
+    fn email_tokens_are_stored_hashed() {
+        let token = email_token();
+
+        assert_eq!(token.len(), 32);
+        assert_ne!(email_token(), token);
+        assert_eq!(hash_email_token(&token), hash_email_token(&token));
+        assert_ne!(hash_email_token(&token), token);
+        assert_eq!(hash_email_token(&token).len(), 64);
+    }
+
+    #[test]
+    fn confirming_an_email_taken_meanwhile_is_a_conflict() {
+        let taken = serde_json::to_value(confirm_email_error(DatabaseError::Conflict(None))).unwrap();
+        assert_eq!(confirm_email_error(DatabaseError::Conflict(None)).http_response().status(), StatusCode::CONFLICT);
+        assert_eq!(taken["error_code"], "EMAIL_TAKEN");
+
+        let unknown = serde_json::to_value(confirm_email_error(DatabaseError::NotFound)).unwrap();
+        assert_eq!(confirm_email_error(DatabaseError::NotFound).http_response().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(unknown["error_code"], "INVALID_TOKEN");
+    }
+
+    async fn break_glass_login(state: State, password: &str) -> (StatusCode, serde_json::Value) {
+        let mut app = test::init_service(App::new()
+            .data(state)
+            .service(web::scope("/api/v1/").service(get_routes()))).await;
+
+        let mut status = StatusCode::OK;
+        let mut body = serde_json::Value::Null;
+        // The second attempt shows whether the first one counted towards the captcha
+        for _ in 0..2 {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/auth/basic")
+                .peer_addr("203.0.113.9:4000".parse().unwrap())
+                .set_json(&serde_json::json!({ "email": "admin@example.com", "password": password }))
+                .to_request();
+            let response = test::call_service(&mut app, req).await;
+            status = response.status();
+            body = serde_json::from_slice(&test::read_body(response).await).unwrap_or_default();
+        }
+        (status, body)
+    }
+
+    #[actix_rt::test]
+    async fn break_glass_logs_in_while_the_database_is_down() {
+        let (status, body) = break_glass_login(State::for_test(break_glass_config()), "correct horse").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["message"], "You have logged in with the break glass credential");
+    }
+
+    #[actix_rt::test]
+    async fn break_glass_is_off_unless_configured() {
+        let (status, _) = break_glass_login(State::for_test(Config::for_test()), "correct horse").await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[actix_rt::test]
+    async fn failed_break_glass_counts_towards_the_captcha() {
+        let mut state = State::for_test(break_glass_config());
+        state.login_captcha = LoginCaptcha::from_config(&Config { captcha_threshold: 1, ..Config::for_test() });
+
+        let (status, body) = break_glass_login(state, "wrong horse").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error_code"], "CAPTCHA_REQUIRED");
+    }
+
+    #[actix_rt::test]
+    async fn login_is_logged_without_the_password() {
+        let (logs, _capture) = capture_logs();
+        let mut app = test::init_service(App::new()
+            .data(State::for_test(break_glass_config()))
+            .wrap_fn(log_request)
+            .service(web::scope("/api/v1/").service(get_routes()))).await;
+
+        // The database is down and the break glass password is wrong
+        let req = test::TestRequest::post()
+            .uri("/api/v1/auth/basic?password=hunter22-query")
+            .set_json(&serde_json::json!({ "email": "admin@example.com", "password": "hunter22-body" }))
+            .to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let logs = logs.text();
+        assert!(logs.contains("failed break glass login"));
+        assert!(logs.contains("query=password=[redacted]"));
+        assert!(!logs.contains("hunter22"));
+    }
 }
\ No newline at end of file
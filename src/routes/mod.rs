@@ -1,2 +1,147 @@
 pub mod user;
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod admin;
+pub mod webauthn;
+pub mod well_known;
+use actix_web::{http::{header::ALLOW, HeaderValue, Method, StatusCode}, HttpRequest, HttpResponse, Scope};
+
+use crate::models::MessageResponse;
+
+/// A route and the methods it accepts. Each module mounts its routes from its `ROUTES`,
+/// so the `Allow` header of 405 responses and the methods CORS preflights allow can't drift from what is mounted
+pub struct Route {
+    /// Handler name, which the route macros also name the resource after
+    pub name: &'static str,
+    pub methods: &'static [Method],
+    pub mount: fn(Scope) -> Scope
+}
+
+/// Every route table, handler names have to be unique across all of them
+const ROUTES: &[&[Route]] = &[user::ROUTES, auth::ROUTES, webauthn::ROUTES, admin::ROUTES, well_known::ROUTES];
+
+/// Scope with each route of a table mounted
+fn mount(scope: Scope, routes: &[Route]) -> Scope {
+    routes.iter().fold(scope, |scope, route| (route.mount)(scope))
+}
+
+/// Methods the route with this handler name accepts
+pub fn route_methods(name: &str) -> Option<&'static [Method]> {
+    ROUTES.iter()
+        .flat_map(|routes| routes.iter())
+        .find(|route| route.name == name)
+        .map(|route| route.methods)
+}
+
+/// Answer requests no route took: 405 when the path exists for other methods, 404 otherwise
+pub async fn fallback(req: HttpRequest) -> HttpResponse {
+    let methods = req.resource_map()
+        .match_name(req.path())
+        .and_then(route_methods);
+
+    match methods {
+        Some(methods) => {
+            let allow = methods.iter().map(Method::as_str).collect::<Vec<_>>().join(", ");
+            let mut response = MessageResponse::new(StatusCode::METHOD_NOT_ALLOWED, "This route doesn't accept that method")
+                .with_error_code("METHOD_NOT_ALLOWED")
+                .http_response();
+            if let Ok(allow) = HeaderValue::from_str(&allow) {
+                response.headers_mut().insert(ALLOW, allow);
+            }
+            response
+        },
+        None => MessageResponse::not_found().http_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, state::State};
+    use actix_web::{test, web, App};
+    use std::collections::HashSet;
+
+    #[actix_rt::test]
+    async fn wrong_method_is_405_with_allow() {
+        let mut app = test::init_service(App::new()
+            .service(web::scope("/api/v1/").service(auth::get_routes()))
+            .default_service(web::route().to(fallback))).await;
+
+        let req = test::TestRequest::get().uri("/api/v1/auth/basic").to_request();
+        let response = test::call_service(&mut app, req).await;
+
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers().get(ALLOW).unwrap(), "POST");
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error_code"], "METHOD_NOT_ALLOWED");
+        assert!(body["message"].is_string());
+    }
+
+    #[actix_rt::test]
+    async fn unknown_path_is_404() {
+        let mut app = test::init_service(App::new()
+            .service(web::scope("/api/v1/").service(auth::get_routes()))
+            .default_service(web::route().to(fallback))).await;
+
+        let req = test::TestRequest::get().uri("/api/v1/auth/nothing").to_request();
+        let response = test::call_service(&mut app, req).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(response.headers().get(ALLOW).is_none());
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error_code"], "NOT_FOUND");
+    }
+
+    #[test]
+    fn handler_names_are_unique() {
+        let mut names = HashSet::new();
+
+        for route in ROUTES.iter().flat_map(|routes| routes.iter()) {
+            assert!(names.insert(route.name), "{} is in more than one route table", route.name);
+        }
+    }
+
+    /// Path of every route, looked up from inside the app since only it knows where they're mounted
+    async fn route_paths(req: HttpRequest) -> HttpResponse {
+        let paths: Vec<(String, String)> = ROUTES.iter()
+            .flat_map(|routes| routes.iter())
+            .map(|route| {
+                let url = req.url_for(route.name, ["1"]).unwrap_or_else(|_| panic!("{} is not mounted", route.name));
+                // Nested scope prefixes are joined as they are, the router doesn't see the doubled slashes
+                (route.name.to_string(), url.path().replace("//", "/"))
+            })
+            .collect();
+
+        HttpResponse::Ok().json(paths)
+    }
+
+    #[actix_rt::test]
+    async fn routes_accept_exactly_their_methods() {
+        let mut app = test::init_service(App::new()
+            .data(State::for_test(Config::for_test()))
+            .service(web::scope("/api/v1/")
+                .service(user::get_routes())
+                .service(auth::get_routes())
+                .service(admin::get_routes()))
+            .service(well_known::get_routes())
+            .route("/route-paths", web::get().to(route_paths))
+            .default_service(web::route().to(fallback))).await;
+
+        let paths: Vec<(String, String)> = test::read_body_json(test::call_service(&mut app, test::TestRequest::get().uri("/route-paths").to_request()).await).await;
+
+        for (name, path) in paths {
+            let methods = route_methods(&name).unwrap();
+
+            for method in methods {
+                let response = test::call_service(&mut app, test::TestRequest::default().method(method.clone()).uri(&path).to_request()).await;
+                assert_ne!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{} {} is listed but not mounted", method, path);
+            }
+
+            // Not accepted by any route
+            let response = test::call_service(&mut app, test::TestRequest::default().method(Method::PATCH).uri(&path).to_request()).await;
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{}", path);
+            assert_eq!(response.headers().get(ALLOW).unwrap(), &methods.iter().map(Method::as_str).collect::<Vec<_>>().join(", "));
+        }
+    }
+}
\ No newline at end of file
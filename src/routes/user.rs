@@ -1,43 +1,58 @@
-use argon2;
 use http::StatusCode;
 
+use crate::database::{DatabaseError, USER_EMAIL_CONSTRAINT};
+use crate::mailer::Outbox;
 use crate::state::State;
 use crate::util::auth;
 use crate::models::*;
 use crate::util;
+use crate::util::tenant::Tenant;
+use super::Route;
 
 use actix_web::*;
+use actix_web::http::Method;
+use validator::Validate;
+
+pub const ROUTES: &[Route] = &[
+    Route { name: "create", methods: &[Method::POST], mount: |scope| scope.service(create) },
+    Route { name: "info", methods: &[Method::GET], mount: |scope| scope.service(info) },
+    Route { name: "password", methods: &[Method::POST], mount: |scope| scope.service(password) },
+];
 
 pub fn get_routes() -> Scope {
-    web::scope("/user/")
-        .service(create)
-        .service(info)
-        .service(password)
+    super::mount(web::scope("/user/"), ROUTES)
 }
 
 #[get("info")]
 async fn info(state: web::Data<State>, auth: auth::middleware::User) -> impl Responder {
-    match state.database.get_user_by_id(auth.0.id as u32).await {
+    match state.database.get_user_by_id(&auth.0.tenant, auth.0.id as u32).await {
         Ok(user_data) => HttpResponse::Ok().json(user_data),
+        // The account was deleted while the token was still valid
+        Err(DatabaseError::NotFound) => MessageResponse::unauthorized_error().http_response(),
         Err(_) => MessageResponse::internal_server_error().http_response()
     }
 }
 
 #[post("password")]
 async fn password(state: web::Data<State>, auth: auth::middleware::User, form: web::Json<PasswordChangeForm>) -> impl Responder {
-    let user = match state.database.get_user_by_id(auth.0.id as u32).await {
+    if let Err(err) = auth.1.not_impersonating("You can't change the password of an impersonated user") {
+        return err;
+    }
+
+    let user = match state.database.get_user_by_id(&auth.0.tenant, auth.0.id as u32).await {
         Ok(data) => data,
+        Err(DatabaseError::NotFound) => return MessageResponse::unauthorized_error(),
         Err(_) => return MessageResponse::internal_server_error()
     };
 
     // Check if password is valid to password hash
-    let matches = match argon2::verify_encoded(&user.password, form.current_password.as_bytes()) {
+    let matches = match util::user::verify_password(&user.password, &form.current_password) {
         Ok(matches) => matches,
-        Err(_) => return MessageResponse::internal_server_error()
+        Err(err) => return err
     };
 
     if !matches {
-        return MessageResponse::new(StatusCode::BAD_REQUEST, "Incorrect password entered");
+        return MessageResponse::bad_request().with_message("Incorrect password entered").with_error_code("INVALID_CREDENTIALS");
     }
 
     // Get new password hash
@@ -46,40 +61,75 @@ async fn password(state: web::Data<State>, auth: auth::middleware::User, form: w
         Err(err) => return err
     };
 
-    match state.database.change_password(auth.0.id as u32, &new_hash).await {
-        Ok(_) => MessageResponse::new(StatusCode::OK, "Password changed successfully"),
-        Err(_) => MessageResponse::internal_server_error()
+    if state.database.change_password(&auth.0.tenant, auth.0.id as u32, &new_hash).await.is_err() {
+        return MessageResponse::internal_server_error();
     }
+
+    MessageResponse::new(StatusCode::OK, "Password changed successfully")
 }
 
 #[post("create")]
-async fn create(state: web::Data<State>, mut form: web::Json<UserCreateForm>) -> impl Responder {
-    // Check if username length is within bounds
-    let username_length = form.username.len();
-    if username_length < 4 {
-        return MessageResponse::new(StatusCode::BAD_REQUEST, "Username too short (minimum 4 characters)");
-    } else if username_length > 15 {
-        return MessageResponse::new(StatusCode::BAD_REQUEST, "Username too long (maximum 15 characters)");
+async fn create(state: web::Data<State>, tenant: Tenant, mut form: web::Json<UserCreateForm>) -> impl Responder {
+    // Check username, email and password format
+    if let Err(errors) = form.validate() {
+        return ValidationResponse::from(errors).http_response();
     }
 
-    // Check if user with same email was found
-    if state.database.get_user_by_email(&form.email).await.is_ok() {
-        return MessageResponse::new(StatusCode::CONFLICT, "An account with that email already exists!");
+    if util::user::is_reserved_username(&form.username, &state.config.reserved_usernames) {
+        return MessageResponse::bad_request().with_message("That username is reserved").with_error_code("USERNAME_RESERVED").http_response();
     }
 
-    // Check if user with same username was found
-    if state.database.get_user_by_username(&form.username).await.is_ok() {
-        return MessageResponse::new(StatusCode::CONFLICT, "An account with that username already exists!");
-    }
-    
     form.password = match util::user::new_password(&form.password) {
         Ok(password_hashed) => password_hashed,
-        Err(err) => return err
+        Err(err) => return err.http_response()
     };
 
-    if state.database.create_user(&form).await.is_err() {
-        return MessageResponse::internal_server_error();
-    }
+    // The unique indexes decide, so two registrations racing for the same email can't both get in
+    let id = match state.database.create_user(&tenant.0, &form).await {
+        Ok(id) => id,
+        Err(DatabaseError::Conflict(Some(constraint))) if constraint == USER_EMAIL_CONSTRAINT => return MessageResponse::conflict().with_message("An account with that email already exists!").with_error_code("EMAIL_TAKEN").http_response(),
+        Err(DatabaseError::Conflict(_)) => return MessageResponse::conflict().with_message("An account with that username already exists!").with_error_code("USERNAME_TAKEN").http_response(),
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    };
+
+    state.webhooks.dispatch("user.registered", serde_json::json!({
+        "id": id,
+        "tenant": tenant.0,
+        "username": form.username
+    }));
+    send_registration_email(&state.outbox, &form);
+
+    MessageResponse::new(StatusCode::OK, "User has successfully been created").http_response()
+}
+
+/// Queued, so a slow mail server never holds up the registration response
+fn send_registration_email(outbox: &Outbox, form: &UserCreateForm) {
+    let body = format!("Hi {},\n\nyour kawaii account was created, you can log in with this email address now.", form.username);
+    outbox.send(&form.email, "Welcome to kawaii", &body);
+}
 
-    MessageResponse::new(StatusCode::OK, "User has successfully been created")
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailer::CapturingMailer;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn registration_queues_email_to_the_new_user() {
+        let mailer = Arc::new(CapturingMailer::default());
+        let outbox = Outbox::new(mailer.clone());
+        let form = UserCreateForm { username: "kawaii".into(), email: "kawaii@example.com".into(), password: "hashed".into() };
+
+        send_registration_email(&outbox, &form);
+        assert!(mailer.sent.lock().unwrap().is_empty(), "the email should be sent in the background");
+
+        rt::time::delay_for(Duration::from_millis(10)).await;
+
+        let sent = mailer.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "kawaii@example.com");
+        assert_eq!(sent[0].1, "Welcome to kawaii");
+        assert!(sent[0].2.contains("kawaii"));
+    }
 }
\ No newline at end of file
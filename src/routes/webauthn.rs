@@ -0,0 +1,154 @@
+use actix_web::*;
+use actix_web::http::{Method, StatusCode};
+use webauthn_rs::proto::{Credential, RegisterPublicKeyCredential};
+
+use crate::{database::DatabaseError, models::*, state::State, util::{auth::{self, *}, tenant::Tenant, webauthn::{Passkeys, credential_key}}};
+use super::Route;
+
+pub const ROUTES: &[Route] = &[
+    Route { name: "register_begin", methods: &[Method::POST], mount: |scope| scope.service(register_begin) },
+    Route { name: "register_finish", methods: &[Method::POST], mount: |scope| scope.service(register_finish) },
+    Route { name: "login_begin", methods: &[Method::POST], mount: |scope| scope.service(login_begin) },
+    Route { name: "login_finish", methods: &[Method::POST], mount: |scope| scope.service(login_finish) },
+];
+
+pub fn get_routes() -> Scope {
+    super::mount(web::scope("webauthn/"), ROUTES)
+}
+
+/// Passkey routes don't exist unless passkeys are configured
+fn passkeys(state: &State) -> Result<&Passkeys, MessageResponse> {
+    state.passkeys.as_ref().ok_or_else(|| MessageResponse::not_found().with_message("Passkeys are not enabled"))
+}
+
+/// Get the challenge to create a new passkey with
+#[post("register/begin")]
+async fn register_begin(state: web::Data<State>, auth: auth::middleware::User) -> impl Responder {
+    let passkeys = match passkeys(&state) {
+        Ok(passkeys) => passkeys,
+        Err(err) => return err.http_response()
+    };
+
+    // A passkey would let the admin log in as the user later on
+    if let Err(err) = auth.1.not_impersonating("You can't add a passkey to an impersonated user") {
+        return err.http_response();
+    }
+
+    match passkeys.begin_registration(auth.0.id, &auth.0.username) {
+        Some(challenge) => HttpResponse::Ok().json(challenge),
+        None => MessageResponse::internal_server_error().http_response()
+    }
+}
+
+/// Store the passkey created for the registration challenge
+#[post("register/finish")]
+async fn register_finish(state: web::Data<State>, auth: auth::middleware::User, response: web::Json<RegisterPublicKeyCredential>) -> impl Responder {
+    let passkeys = match passkeys(&state) {
+        Ok(passkeys) => passkeys,
+        Err(err) => return err
+    };
+
+    if let Err(err) = auth.1.not_impersonating("You can't add a passkey to an impersonated user") {
+        return err;
+    }
+
+    let credential = match passkeys.finish_registration(auth.0.id, &response) {
+        Some(credential) => credential,
+        None => return MessageResponse::bad_request().with_message("The passkey could not be verified").with_error_code("INVALID_PASSKEY")
+    };
+
+    let serialized = match serde_json::to_string(&credential) {
+        Ok(serialized) => serialized,
+        Err(_) => return MessageResponse::internal_server_error()
+    };
+
+    match state.database.create_webauthn_credential(&auth.0.tenant, auth.0.id, &credential_key(&credential.cred_id), &serialized).await {
+        Ok(_) => MessageResponse::new(StatusCode::OK, "Passkey registered successfully"),
+        Err(DatabaseError::Conflict(_)) => MessageResponse::conflict().with_message("That passkey is already registered"),
+        Err(_) => MessageResponse::internal_server_error()
+    }
+}
+
+/// Get the challenge to log in with one of an account's passkeys
+#[post("login/begin")]
+async fn login_begin(state: web::Data<State>, tenant: Tenant, form: web::Json<PasskeyLoginForm>) -> impl Responder {
+    let passkeys = match passkeys(&state) {
+        Ok(passkeys) => passkeys,
+        Err(err) => return err.http_response()
+    };
+
+    let no_passkeys = MessageResponse::bad_request().with_message("No passkeys are registered for that account").with_error_code("NO_PASSKEYS");
+
+    let user_data = match state.database.get_user_by_email(&tenant.0, &form.email).await {
+        Ok(user_data) => user_data,
+        Err(DatabaseError::NotFound) => return no_passkeys.http_response(),
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    };
+
+    let credentials: Vec<Credential> = match state.database.get_webauthn_credentials(&tenant.0, user_data.id).await {
+        Ok(stored) => stored.iter().filter_map(|credential| serde_json::from_str(credential).ok()).collect(),
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    };
+
+    if credentials.is_empty() {
+        return no_passkeys.http_response();
+    }
+
+    match passkeys.begin_login(user_data.id, &tenant.0, credentials) {
+        Some((login_id, challenge)) => HttpResponse::Ok().json(PasskeyChallenge { login_id, challenge }),
+        None => MessageResponse::internal_server_error().http_response()
+    }
+}
+
+/// Log in with the signed challenge, setting the same cookie as a password login
+#[post("login/finish")]
+async fn login_finish(state: web::Data<State>, tenant: Tenant, form: web::Json<PasskeyLoginFinishForm>) -> impl Responder {
+    let passkeys = match passkeys(&state) {
+        Ok(passkeys) => passkeys,
+        Err(err) => return err.http_response()
+    };
+
+    let login = match passkeys.finish_login(&form.login_id, &form.credential) {
+        Some(login) if login.tenant == tenant.0 => login,
+        _ => {
+            tracing::info!(tenant = %tenant.0, "passkey login failed");
+            return MessageResponse::bad_request().with_message("The passkey could not be verified").with_error_code("INVALID_CREDENTIALS").http_response();
+        }
+    };
+
+    // Keep the signature counter current so cloned authenticators can be noticed
+    let stored = match state.database.get_webauthn_credentials(&tenant.0, login.user_id).await {
+        Ok(stored) => stored,
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    };
+
+    let credential = stored.iter()
+        .filter_map(|credential| serde_json::from_str::<Credential>(credential).ok())
+        .find(|credential| credential.cred_id == login.cred_id);
+
+    if let Some(mut credential) = credential {
+        credential.counter = login.counter;
+        let updated = match serde_json::to_string(&credential) {
+            Ok(updated) => updated,
+            Err(_) => return MessageResponse::internal_server_error().http_response()
+        };
+
+        if state.database.update_webauthn_credential(&tenant.0, &credential_key(&credential.cred_id), &updated).await.is_err() {
+            return MessageResponse::internal_server_error().http_response();
+        }
+    }
+
+    let user_data = match state.database.get_user_by_id(&tenant.0, login.user_id as u32).await {
+        Ok(user_data) => user_data,
+        Err(_) => return MessageResponse::internal_server_error().http_response()
+    };
+
+    tracing::info!(tenant = %tenant.0, user_id = user_data.id, "passkey login succeeded");
+
+    match session_cookie(&state, &user_data).await {
+        Ok(cookie) => HttpResponse::Ok()
+            .cookie(cookie)
+            .json(MessageResponse::new(StatusCode::OK, "You have logged in")),
+        Err(err) => err.http_response()
+    }
+}
\ No newline at end of file
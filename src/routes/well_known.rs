@@ -0,0 +1,22 @@
+use actix_web::*;
+use actix_web::http::Method;
+
+use crate::{models::MessageResponse, state::State};
+use super::Route;
+
+pub const ROUTES: &[Route] = &[
+    Route { name: "jwks", methods: &[Method::GET], mount: |scope| scope.service(jwks) },
+];
+
+pub fn get_routes() -> Scope {
+    super::mount(web::scope("/.well-known/"), ROUTES)
+}
+
+/// Public keys for verifying auth tokens, empty when tokens are signed with a secret
+#[get("jwks.json")]
+async fn jwks(state: web::Data<State>) -> impl Responder {
+    match state.jwt_keys.jwks() {
+        Ok(jwks) => HttpResponse::Ok().json(jwks),
+        Err(_) => MessageResponse::internal_server_error().http_response()
+    }
+}
\ No newline at end of file
@@ -1,9 +1,33 @@
-use crate::{database::Database, storage::Storage};
-use hmac::Hmac;
-use sha2::Sha256;
+use crate::{config::Config, database::Database, mailer::Outbox, storage::Storage, util::{auth::JwtKeys, captcha::LoginCaptcha, login_ips::LoginIps, rate_limit::RateLimiter, webauthn::Passkeys}, webhook::Webhooks};
 
 pub struct State {
+    pub config: Config,
     pub database: Database,
     pub storage: Storage,
-    pub jwt_key: Hmac<Sha256>
+    pub jwt_keys: JwtKeys,
+    pub webhooks: Webhooks,
+    pub login_captcha: Option<LoginCaptcha>,
+    pub login_ips: Option<LoginIps>,
+    pub passkeys: Option<Passkeys>,
+    pub rate_limiter: Option<Box<dyn RateLimiter>>,
+    pub outbox: Outbox
+}
+
+#[cfg(test)]
+impl State {
+    /// State for handler tests, every query fails like it would with the database down
+    pub fn for_test(config: Config) -> Self {
+        State {
+            database: Database::unreachable(),
+            storage: Storage::new(&config.s3_bucket, &config.s3_access_key, &config.s3_secret_key, config.s3_region.clone()),
+            jwt_keys: JwtKeys::from_config(&config),
+            webhooks: Webhooks::new(&[], ""),
+            login_captcha: None,
+            login_ips: None,
+            passkeys: None,
+            rate_limiter: None,
+            outbox: Outbox::new(std::sync::Arc::new(crate::mailer::NoopMailer)),
+            config
+        }
+    }
 }
\ No newline at end of file
@@ -1,18 +1,344 @@
-use actix_web::{Error, HttpMessage, HttpRequest, web::Data};
-use hmac::Hmac;
-use jwt::{VerifyWithKey, SignWithKey, RegisteredClaims};
+use actix_web::{Error, HttpRequest, http::Cookie, web::Data};
+use hmac::{Hmac, NewMac};
+use jwt::{AlgorithmType, Header, PKeyWithDigest, RegisteredClaims, SignWithKey, SigningAlgorithm, Token, VerifyWithKey, VerifyingAlgorithm};
+use openssl::{bn::{BigNum, BigNumContext}, error::ErrorStack, hash::MessageDigest, nid::Nid, pkey::{Id, PKey, Private, Public}};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use sha2::Sha256;
+use std::collections::HashMap;
+use url::Url;
 
+use crate::config::{Config, JwtAlgorithm};
+use crate::database::DatabaseError;
 use crate::state::State;
-use crate::models::MessageResponse;
-use crate::models::user::UserData;
+use crate::util::cookies::{auth_cookie, auth_token};
+use crate::util::tenant::{resolve_tenant, unknown_tenant};
+use crate::models::{Jwk, JwkSet, MessageResponse};
+use crate::models::user::{UserData, UserRole};
+
+/// Keys used to sign and verify auth tokens
+pub enum JwtKey {
+    /// HS256, the same secret signs and verifies
+    Hmac(Box<Hmac<Sha256>>),
+    /// RS256 or ES256, only the private key can sign
+    Asymmetric {
+        signing: PKeyWithDigest<Private>,
+        verifying: PKeyWithDigest<Public>,
+    },
+}
+
+impl JwtKey {
+    /// Load keys for the configured algorithm, panics on a bad config like the rest of startup
+    pub fn from_config(config: &Config) -> Self {
+        let key_id = match config.jwt_algorithm {
+            JwtAlgorithm::Hs256 => {
+                // Without a configured secret every restart logs everyone out
+                let secret = match &config.jwt_secret {
+                    Some(secret) => secret.as_bytes().to_vec(),
+                    None => rand::thread_rng().gen::<[u8; 32]>().to_vec()
+                };
+                let key = Hmac::new_varkey(&secret).expect("Could not generate JWT key");
+                return JwtKey::Hmac(Box::new(key));
+            },
+            JwtAlgorithm::Rs256 => Id::RSA,
+            JwtAlgorithm::Es256 => Id::EC,
+        };
+
+        let private_path = config.jwt_private_key_path.as_ref().expect("JWT_PRIVATE_KEY is required for asymmetric JWT algorithms");
+        let public_path = config.jwt_public_key_path.as_ref().expect("JWT_PUBLIC_KEY is required for asymmetric JWT algorithms");
+
+        let private = PKey::private_key_from_pem(&std::fs::read(private_path).expect("Could not read JWT private key"))
+            .expect("Invalid JWT private key");
+        let public = PKey::public_key_from_pem(&std::fs::read(public_path).expect("Could not read JWT public key"))
+            .expect("Invalid JWT public key");
+
+        if private.id() != key_id || public.id() != key_id {
+            panic!("JWT keys do not match the configured algorithm");
+        }
+        if !public.public_eq(&private) {
+            panic!("JWT public key does not belong to the private key");
+        }
+        // ES256 is only defined for the P-256 curve
+        if key_id == Id::EC && private.ec_key().expect("Invalid JWT private key").group().curve_name() != Some(Nid::X9_62_PRIME256V1) {
+            panic!("ES256 requires a P-256 key");
+        }
+
+        JwtKey::Asymmetric {
+            signing: PKeyWithDigest { digest: MessageDigest::sha256(), key: private },
+            verifying: PKeyWithDigest { digest: MessageDigest::sha256(), key: public },
+        }
+    }
+}
+
+impl JwtKey {
+    /// Name of the signing algorithm as used in JWT headers
+    pub fn algorithm_name(&self) -> &'static str {
+        match SigningAlgorithm::algorithm_type(self) {
+            AlgorithmType::Rs256 => "RS256",
+            AlgorithmType::Es256 => "ES256",
+            _ => "HS256",
+        }
+    }
+
+    /// Public key to publish, HMAC secrets are never published
+    fn public_key(&self) -> Option<&PKey<Public>> {
+        match self {
+            JwtKey::Hmac(_) => None,
+            JwtKey::Asymmetric { verifying, .. } => Some(&verifying.key),
+        }
+    }
+}
+
+/// Key that tokens were signed with before it was rotated out, can only verify
+pub enum RetiredKey {
+    Hmac(Box<Hmac<Sha256>>),
+    Public(PKeyWithDigest<Public>),
+}
+
+impl RetiredKey {
+    /// Load a retired key from a file, the HMAC secret for HS256 or the PEM public key otherwise
+    fn load(algorithm: JwtAlgorithm, path: &str) -> Self {
+        let data = std::fs::read(path).expect("Could not read retired JWT key");
+
+        let key_id = match algorithm {
+            JwtAlgorithm::Hs256 => {
+                let secret = String::from_utf8(data).expect("Retired JWT secret is not text");
+                let key = Hmac::new_varkey(secret.trim().as_bytes()).expect("Invalid retired JWT secret");
+                return RetiredKey::Hmac(Box::new(key));
+            },
+            JwtAlgorithm::Rs256 => Id::RSA,
+            JwtAlgorithm::Es256 => Id::EC,
+        };
+
+        let public = PKey::public_key_from_pem(&data).expect("Invalid retired JWT public key");
+        if public.id() != key_id {
+            panic!("Retired JWT key {} does not match the configured algorithm", path);
+        }
+
+        RetiredKey::Public(PKeyWithDigest { digest: MessageDigest::sha256(), key: public })
+    }
+}
+
+impl VerifyingAlgorithm for RetiredKey {
+    fn algorithm_type(&self) -> AlgorithmType {
+        match self {
+            RetiredKey::Hmac(key) => VerifyingAlgorithm::algorithm_type(key.as_ref()),
+            RetiredKey::Public(key) => key.algorithm_type(),
+        }
+    }
+
+    fn verify_bytes(&self, header: &str, claims: &str, signature: &[u8]) -> Result<bool, jwt::Error> {
+        match self {
+            RetiredKey::Hmac(key) => key.as_ref().verify_bytes(header, claims, signature),
+            RetiredKey::Public(key) => key.verify_bytes(header, claims, signature),
+        }
+    }
+}
+
+/// The key new tokens are signed with, and the retired keys older tokens
+/// are still accepted with, by key id (`kid`)
+pub struct JwtKeys {
+    key_id: String,
+    active: JwtKey,
+    retired: HashMap<String, RetiredKey>,
+}
+
+impl JwtKeys {
+    pub fn from_config(config: &Config) -> Self {
+        let retired = config.jwt_retired_keys.iter()
+            .map(|(key_id, path)| {
+                if *key_id == config.jwt_key_id {
+                    panic!("JWT key id {} is both active and retired", key_id);
+                }
+                (key_id.clone(), RetiredKey::load(config.jwt_algorithm, path))
+            })
+            .collect();
+
+        JwtKeys {
+            key_id: config.jwt_key_id.clone(),
+            active: JwtKey::from_config(config),
+            retired,
+        }
+    }
+
+    /// Name of the signing algorithm as used in JWT headers
+    pub fn algorithm_name(&self) -> &'static str {
+        self.active.algorithm_name()
+    }
+
+    /// Sign claims with the active key, naming it in the header
+    fn sign(&self, claims: AuthClaims) -> Result<String, jwt::Error> {
+        let header = Header {
+            algorithm: SigningAlgorithm::algorithm_type(&self.active),
+            key_id: Some(self.key_id.clone()),
+            ..Default::default()
+        };
+
+        Ok(Token::new(header, claims).sign_with_key(&self.active)?.into())
+    }
+
+    /// Verify a token with the key named in its header.
+    /// Tokens without a key id were issued before rotation and are checked with the active key
+    pub fn verify(&self, token: &str) -> Result<AuthClaims, jwt::Error> {
+        let token: Token<Header, AuthClaims, _> = Token::parse_unverified(token)?;
+
+        let verified = match token.header().key_id.clone() {
+            Some(key_id) if key_id != self.key_id => {
+                let key = self.retired.get(&key_id).ok_or(jwt::Error::NoKeyWithKeyId(key_id))?;
+                token.verify_with_key(key)?
+            },
+            _ => token.verify_with_key(&self.active)?,
+        };
+
+        let (_, claims) = verified.into();
+        Ok(claims)
+    }
+
+    /// Public keys to publish, including retired ones tokens may still be signed with
+    pub fn jwks(&self) -> Result<JwkSet, ErrorStack> {
+        let active = self.active.public_key().map(|key| (self.key_id.as_str(), key));
+        let retired = self.retired.iter().filter_map(|(key_id, key)| match key {
+            RetiredKey::Hmac(_) => None,
+            RetiredKey::Public(key) => Some((key_id.as_str(), &key.key)),
+        });
+
+        let keys = active.into_iter()
+            .chain(retired)
+            .map(|(key_id, key)| public_jwk(key_id, key))
+            .collect::<Result<Vec<Jwk>, ErrorStack>>()?;
+
+        Ok(JwkSet { keys })
+    }
+}
+
+/// Public key in JWK format
+fn public_jwk(key_id: &str, public: &PKey<Public>) -> Result<Jwk, ErrorStack> {
+    let jwk = match public.id() {
+        Id::RSA => {
+            let rsa = public.rsa()?;
+            Jwk {
+                kty: "RSA",
+                key_use: "sig",
+                alg: "RS256",
+                kid: key_id.to_string(),
+                n: Some(base64_url(&rsa.n().to_vec())),
+                e: Some(base64_url(&rsa.e().to_vec())),
+                crv: None,
+                x: None,
+                y: None,
+            }
+        },
+        _ => {
+            let ec = public.ec_key()?;
+            let mut x = BigNum::new()?;
+            let mut y = BigNum::new()?;
+            let mut context = BigNumContext::new()?;
+            ec.public_key().affine_coordinates_gfp(ec.group(), &mut x, &mut y, &mut context)?;
+
+            Jwk {
+                kty: "EC",
+                key_use: "sig",
+                alg: "ES256",
+                kid: key_id.to_string(),
+                n: None,
+                e: None,
+                crv: Some("P-256"),
+                // P-256 coordinates are always 32 bytes
+                x: Some(base64_url(&x.to_vec_padded(32)?)),
+                y: Some(base64_url(&y.to_vec_padded(32)?)),
+            }
+        }
+    };
+
+    Ok(jwk)
+}
+
+/// Unpadded url safe base64, as used by JWK
+fn base64_url(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+impl SigningAlgorithm for JwtKey {
+    fn algorithm_type(&self) -> AlgorithmType {
+        match self {
+            JwtKey::Hmac(key) => SigningAlgorithm::algorithm_type(key.as_ref()),
+            JwtKey::Asymmetric { signing, .. } => signing.algorithm_type(),
+        }
+    }
+
+    fn sign(&self, header: &str, claims: &str) -> Result<String, jwt::Error> {
+        match self {
+            JwtKey::Hmac(key) => key.as_ref().sign(header, claims),
+            JwtKey::Asymmetric { signing, .. } => signing.sign(header, claims),
+        }
+    }
+}
+
+impl VerifyingAlgorithm for JwtKey {
+    fn algorithm_type(&self) -> AlgorithmType {
+        match self {
+            JwtKey::Hmac(key) => VerifyingAlgorithm::algorithm_type(key.as_ref()),
+            JwtKey::Asymmetric { verifying, .. } => verifying.algorithm_type(),
+        }
+    }
+
+    fn verify_bytes(&self, header: &str, claims: &str, signature: &[u8]) -> Result<bool, jwt::Error> {
+        match self {
+            JwtKey::Hmac(key) => key.as_ref().verify_bytes(header, claims, signature),
+            JwtKey::Asymmetric { verifying, .. } => verifying.verify_bytes(header, claims, signature),
+        }
+    }
+}
+
+/// Claims stored in an auth token
+#[derive(Serialize, Deserialize)]
+pub struct AuthClaims {
+    #[serde(flatten)]
+    pub registered: RegisteredClaims,
+
+    /// Tenant the token was issued in, so it can't be replayed on another
+    pub tenant: String,
+
+    /// Id of the admin who issued this token to act as the subject
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator: Option<i32>,
+
+    /// Token version of the user when this was issued
+    #[serde(default)]
+    pub version: i32,
+
+    /// Issued to the break glass credential rather than an account
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub break_glass: bool,
+}
+
+/// How the current request was authenticated
+pub struct Session {
+    /// Admin acting as the user, for impersonation sessions
+    pub impersonator: Option<i32>,
+}
+
+impl Session {
+    /// Guard for what only the account owner may do. Admins acting as a user can see what they see,
+    /// but not take over the account, so impersonation sessions get a 403 with `message`
+    pub fn not_impersonating(&self, message: &str) -> Result<(), MessageResponse> {
+        match self.impersonator {
+            Some(_) => Err(MessageResponse::forbidden().with_message(message)),
+            None => Ok(())
+        }
+    }
+    /// Who an audit log entry should name for an action of `user_id`, the admin when impersonating
+    pub fn actor(&self, user_id: i32) -> i32 {
+        self.impersonator.unwrap_or(user_id)
+    }
+}
 
 /// Generate auth middleware for a UserRole.
 /// This implementation will allow the specified role or lower access level roles to access a resource
 macro_rules! define_auth {
     ($name:ident, $role_enum:expr) => {
         // Authentication middleware for this role. This will also work for roles at a lower access level
-        pub struct $name(pub $crate::models::user::UserData);
+        pub struct $name(pub $crate::models::user::UserData, pub $crate::util::auth::Session);
 
         impl actix_web::FromRequest for $name {
             type Error = actix_web::Error;
@@ -23,8 +349,8 @@ macro_rules! define_auth {
                 let req = req.clone();
 
                 Box::pin(async move {
-                    let user_data = match $crate::util::auth::get_auth_data(req).await {
-                        Ok(user_data) => user_data,
+                    let (user_data, session) = match $crate::util::auth::get_auth_data(req).await {
+                        Ok(auth_data) => auth_data,
                         Err(err) => return Err(err)
                     };
 
@@ -32,7 +358,7 @@ macro_rules! define_auth {
                         return Err(actix_web::Error::from($crate::models::MessageResponse::unauthorized_error()))
                     }
 
-                    Ok($name(user_data))
+                    Ok($name(user_data, session))
                 })
             }
         }
@@ -40,23 +366,23 @@ macro_rules! define_auth {
 }
 
 /// Get data from user based on request
-async fn get_auth_data(req: HttpRequest) -> Result<UserData, actix_web::Error> {
+async fn get_auth_data(req: HttpRequest) -> Result<(UserData, Session), actix_web::Error> {
     let state = req.app_data::<Data<State>>().expect("State was not found");
 
-    let jwt_token = match req.cookie("auth-token") {
+    let jwt_token = match auth_token(&req, &state.config) {
         Some(jwt_token) => jwt_token,
         // Token could not be found
         None => return Err(Error::from(MessageResponse::unauthorized_error()))
     };
 
-    // Try to verify token
-    let claim: RegisteredClaims = match jwt_token.value().verify_with_key(&state.jwt_key) {
-        Ok(claim) => claim,
-        // Token verification failed
-        Err(_) => return Err(Error::from(MessageResponse::unauthorized_error()))
+    let tenant = match resolve_tenant(&req, &state.config) {
+        Some(tenant) => tenant,
+        None => return Err(Error::from(unknown_tenant()))
     };
 
-    let user_id = match claim.subject {
+    let claim = verified_claims(&jwt_token, &tenant, &state.config, &state.jwt_keys, chrono::Utc::now().timestamp() as u64)?;
+
+    let user_id: u32 = match claim.registered.subject {
         Some(data) => {
             match data.parse() {
                 Ok(parsed) => parsed,
@@ -66,9 +392,28 @@ async fn get_auth_data(req: HttpRequest) -> Result<UserData, actix_web::Error> {
         None => return Err(Error::from(MessageResponse::internal_server_error()))
     };
 
-    match state.database.get_user_by_id(user_id).await {
-        Ok(data) => Ok(data),
-        Err(_) => return Err(Error::from(MessageResponse::internal_server_error()))
+    let session = Session {
+        impersonator: claim.impersonator,
+    };
+
+    // Break glass sessions have no account behind them
+    if claim.break_glass {
+        return match break_glass_user(&state.config, &tenant) {
+            Some(user) => Ok((user, session)),
+            // The credential has been removed since
+            None => Err(Error::from(MessageResponse::unauthorized_error()))
+        };
+    }
+
+    tracing::Span::current().record("user_id", &user_id);
+
+    match state.database.get_user_by_id(&tenant, user_id).await {
+        // Sessions from before the version was bumped are logged out
+        Ok(data) if !is_current_version(&data, claim.version) => Err(Error::from(MessageResponse::unauthorized_error())),
+        Ok(data) => Ok((data, session)),
+        // User no longer exists
+        Err(DatabaseError::NotFound) => Err(Error::from(MessageResponse::unauthorized_error())),
+        Err(_) => Err(Error::from(MessageResponse::internal_server_error()))
     }
 }
 
@@ -81,13 +426,457 @@ pub mod middleware {
 }
 
 // Sign a JWT token and get a string
-pub fn create_jwt_string(id: i32, issuer: &str, timestamp: i64, key: &Hmac<Sha256>) -> Result<String, jwt::Error> {
-    let claims = RegisteredClaims {
-        issuer: Some(issuer.into()),
-        subject: Some(id.to_string().into()),
-        expiration: Some(timestamp as u64),
-        ..Default::default()
+pub fn create_jwt_string(id: i32, tenant: &str, version: i32, impersonator: Option<i32>, issuer: &str, timestamp: i64, keys: &JwtKeys) -> Result<String, jwt::Error> {
+    let claims = AuthClaims {
+        registered: RegisteredClaims {
+            issuer: Some(issuer.into()),
+            subject: Some(id.to_string().into()),
+            expiration: Some(timestamp as u64),
+            issued_at: Some(chrono::Utc::now().timestamp() as u64),
+            ..Default::default()
+        },
+        tenant: tenant.to_string(),
+        impersonator,
+        version,
+        break_glass: false,
     };
 
-    claims.sign_with_key(key)
+    keys.sign(claims)
 }
+
+/// Claims of a token that is signed by one of our keys, was issued in `tenant` and is still current
+fn verified_claims(token: &str, tenant: &str, config: &Config, keys: &JwtKeys, now: u64) -> Result<AuthClaims, MessageResponse> {
+    let claims = keys.verify(token).map_err(|_| MessageResponse::unauthorized_error())?;
+
+    // Token was issued by another tenant
+    if claims.tenant != tenant {
+        return Err(MessageResponse::unauthorized_error());
+    }
+
+    if !is_token_current(&claims.registered, config, now) {
+        return Err(MessageResponse::unauthorized_error());
+    }
+
+    Ok(claims)
+}
+
+/// Check that a token hasn't expired and isn't older than the maximum token age
+fn is_token_current(claims: &RegisteredClaims, config: &Config, now: u64) -> bool {
+    if claims.expiration.is_some_and(|expiration| expiration <= now) {
+        return false;
+    }
+
+    let max_age = match config.max_token_age_hours {
+        Some(hours) => hours * 3600,
+        None => return true
+    };
+
+    match claims.issued_at {
+        Some(issued_at) => now.saturating_sub(issued_at) <= max_age,
+        // Tokens from before issue times were recorded
+        None => config.accept_tokens_without_iat
+    }
+}
+
+/// Users whose earlier sessions end when they log in again
+fn is_single_session(config: &Config, user: &UserData) -> bool {
+    config.single_session_roles.contains(&user.role)
+}
+
+/// Check a token carries the version the user is at, anything else was issued before a bump
+fn is_current_version(user: &UserData, version: i32) -> bool {
+    user.token_version == version
+}
+
+/// Create a signed JWT token for the break glass credential
+pub fn create_break_glass_jwt(tenant: &str, issuer: &str, timestamp: i64, keys: &JwtKeys) -> Result<String, jwt::Error> {
+    let claims = AuthClaims {
+        registered: RegisteredClaims {
+            issuer: Some(issuer.into()),
+            subject: Some("0".into()),
+            expiration: Some(timestamp as u64),
+            issued_at: Some(chrono::Utc::now().timestamp() as u64),
+            ..Default::default()
+        },
+        tenant: tenant.to_string(),
+        impersonator: None,
+        version: 0,
+        break_glass: true,
+    };
+
+    keys.sign(claims)
+}
+
+/// Admin standing in for the break glass credential, while it's configured
+fn break_glass_user(config: &Config, tenant: &str) -> Option<UserData> {
+    let break_glass = config.break_glass.as_ref()?;
+
+    Some(UserData {
+        id: 0,
+        tenant: tenant.to_string(),
+        password: break_glass.password_hash.clone(),
+        username: "break-glass".into(),
+        email: break_glass.email.clone(),
+        verified: true,
+        role: UserRole::Admin,
+        token_version: 0,
+        password_changed_at: chrono::Utc::now().naive_utc(),
+    })
+}
+
+/// Cookie with a week long session for a user who just logged in
+pub async fn session_cookie(state: &State, user: &UserData) -> Result<Cookie<'static>, MessageResponse> {
+    let expire_time = (chrono::Utc::now() + chrono::Duration::weeks(1)).timestamp();
+
+    // In single session mode the new token is the only one that works
+    let version = if is_single_session(&state.config, user) {
+        state.database.bump_token_version(&user.tenant, user.id).await.map_err(|_| MessageResponse::internal_server_error())?
+    } else {
+        user.token_version
+    };
+
+    let jwt = create_jwt_string(user.id, &user.tenant, version, None, &state.config.jwt_issuer, expire_time, &state.jwt_keys)
+        .map_err(|_| MessageResponse::internal_server_error())?;
+
+    Ok(auth_cookie(&state.config, jwt, expire_time))
+}
+
+/// Where a login should send the browser, `None` when no `next` was given or it isn't allowed
+pub fn login_redirect<'a>(next: Option<&'a str>, allowlist: &[String]) -> Option<&'a str> {
+    next.filter(|next| is_allowed_redirect(next, allowlist))
+}
+
+/// Check if a login may redirect to `next`.
+/// Relative paths must match an allowlisted path, absolute URLs must be http(s) on an allowlisted host
+pub fn is_allowed_redirect(next: &str, allowlist: &[String]) -> bool {
+    // Browsers treat "//host" and "/\host" as absolute urls to another site
+    if next.starts_with("//") || next.contains('\\') || next.chars().any(char::is_control) {
+        return false;
+    }
+
+    if next.starts_with('/') {
+        // "/dashboard/../admin" starts with an allowlisted path but browsers resolve it to another one
+        let path = next.split(&['?', '#'][..]).next().unwrap_or_default();
+        let dot_segment = path.split('/').any(|segment| {
+            let segment = segment.to_ascii_lowercase().replace("%2e", ".");
+            segment == "." || segment == ".."
+        });
+        if dot_segment {
+            return false;
+        }
+
+        return allowlist.iter()
+            .filter(|entry| entry.starts_with('/'))
+            .any(|path| {
+                next == path
+                    || (path.ends_with('/') && next.starts_with(path.as_str()))
+                    || next.strip_prefix(path.as_str()).is_some_and(|rest| rest.starts_with(&['/', '?', '#'][..]))
+            });
+    }
+
+    let url = match Url::parse(next) {
+        Ok(url) => url,
+        Err(_) => return false
+    };
+
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return false;
+    }
+
+    match url.host_str() {
+        Some(host) => allowlist.iter()
+            .filter(|entry| !entry.starts_with('/'))
+            .any(|entry| entry.eq_ignore_ascii_case(host)),
+        None => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use openssl::{ec::{EcGroup, EcKey}, rsa::Rsa};
+
+    /// Keys are only loaded from files, so write them to one unique to the test
+    fn key_file(name: &str, data: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("kawaii-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn rsa_key() -> PKey<Private> {
+        PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap()
+    }
+
+    fn ec_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn asymmetric_config(algorithm: JwtAlgorithm, name: &str, private: &PKey<Private>) -> Config {
+        Config {
+            jwt_algorithm: algorithm,
+            jwt_private_key_path: Some(key_file(name, &private.private_key_to_pem_pkcs8().unwrap())),
+            jwt_public_key_path: Some(key_file(&format!("{}.pub", name), &private.public_key_to_pem().unwrap())),
+            ..Config::for_test()
+        }
+    }
+
+    #[test]
+    fn jwt_round_trip_with_test_config() {
+        let config = Config::for_test();
+        let keys = JwtKeys::from_config(&config);
+
+        let token = create_jwt_string(7, "main", 2, None, &config.jwt_issuer, 4_000_000_000, &keys).unwrap();
+        let claims = keys.verify(&token).unwrap();
+
+        assert_eq!(claims.registered.subject.as_deref(), Some("7"));
+        assert_eq!(claims.tenant, "main");
+        assert_eq!(claims.version, 2);
+        assert!(claims.registered.issued_at.is_some());
+    }
+
+    #[test]
+    fn jwt_from_another_secret_is_rejected() {
+        let config = Config::for_test();
+        let other = Config { jwt_secret: Some("another secret".into()), ..Config::for_test() };
+
+        let token = create_jwt_string(7, "main", 0, None, &config.jwt_issuer, 4_000_000_000, &JwtKeys::from_config(&other)).unwrap();
+
+        assert!(JwtKeys::from_config(&config).verify(&token).is_err());
+    }
+
+    #[test]
+    fn asymmetric_jwt_round_trip() {
+        for (algorithm, name, key) in [(JwtAlgorithm::Rs256, "RS256", rsa_key()), (JwtAlgorithm::Es256, "ES256", ec_key())] {
+            let config = asymmetric_config(algorithm, &format!("round-trip-{}", name), &key);
+            let keys = JwtKeys::from_config(&config);
+
+            let token = create_jwt_string(7, "main", 0, None, &config.jwt_issuer, 4_000_000_000, &keys).unwrap();
+
+            assert_eq!(keys.algorithm_name(), name);
+            assert_eq!(keys.verify(&token).unwrap().registered.subject.as_deref(), Some("7"));
+        }
+    }
+
+    #[test]
+    fn asymmetric_jwt_from_another_key_is_rejected() {
+        for (algorithm, name, key, other) in [(JwtAlgorithm::Rs256, "RS256", rsa_key(), rsa_key()), (JwtAlgorithm::Es256, "ES256", ec_key(), ec_key())] {
+            let config = asymmetric_config(algorithm, &format!("signer-{}", name), &key);
+            let other = asymmetric_config(algorithm, &format!("other-{}", name), &other);
+
+            let token = create_jwt_string(7, "main", 0, None, &config.jwt_issuer, 4_000_000_000, &JwtKeys::from_config(&config)).unwrap();
+
+            assert!(JwtKeys::from_config(&other).verify(&token).is_err());
+        }
+    }
+
+    #[test]
+    fn jwt_signed_with_a_retired_key_still_verifies() {
+        let old = Config { jwt_key_id: "old".into(), ..Config::for_test() };
+        let new = Config {
+            jwt_key_id: "new".into(),
+            jwt_secret: Some("new secret".into()),
+            jwt_retired_keys: vec![("old".into(), key_file("retired-old", b"test secret\n"))],
+            ..Config::for_test()
+        };
+
+        let token = create_jwt_string(7, "main", 0, None, &old.jwt_issuer, 4_000_000_000, &JwtKeys::from_config(&old)).unwrap();
+
+        assert_eq!(JwtKeys::from_config(&new).verify(&token).unwrap().registered.subject.as_deref(), Some("7"));
+    }
+
+    #[test]
+    fn jwt_signed_with_a_removed_key_is_rejected() {
+        let old = Config { jwt_key_id: "old".into(), ..Config::for_test() };
+        let new = Config { jwt_key_id: "new".into(), ..Config::for_test() };
+
+        let token = create_jwt_string(7, "main", 0, None, &old.jwt_issuer, 4_000_000_000, &JwtKeys::from_config(&old)).unwrap();
+
+        // Same secret, but nothing is published under the old key id any more
+        assert!(matches!(JwtKeys::from_config(&new).verify(&token), Err(jwt::Error::NoKeyWithKeyId(key_id)) if key_id == "old"));
+    }
+
+    #[test]
+    fn rsa_jwks_lists_active_and_retired_keys() {
+        let key = rsa_key();
+        let retired = rsa_key();
+        let config = Config {
+            jwt_key_id: "new".into(),
+            jwt_retired_keys: vec![("old".into(), key_file("jwks-retired.pub", &retired.public_key_to_pem().unwrap()))],
+            ..asymmetric_config(JwtAlgorithm::Rs256, "jwks-rsa", &key)
+        };
+
+        let jwks = JwtKeys::from_config(&config).jwks().unwrap();
+        assert_eq!(jwks.keys.len(), 2);
+
+        let active = jwks.keys.iter().find(|jwk| jwk.kid == "new").unwrap();
+        assert_eq!((active.kty, active.alg, active.key_use), ("RSA", "RS256", "sig"));
+        assert_eq!(active.n.as_deref(), Some(base64_url(&key.rsa().unwrap().n().to_vec()).as_str()));
+        assert_eq!(active.e.as_deref(), Some("AQAB"));
+
+        let old = jwks.keys.iter().find(|jwk| jwk.kid == "old").unwrap();
+        assert_eq!(old.n.as_deref(), Some(base64_url(&retired.rsa().unwrap().n().to_vec()).as_str()));
+    }
+
+    #[test]
+    fn ec_jwks_coordinates_are_padded() {
+        // Find a key with a coordinate that has a leading zero byte, about one in 128 do
+        let mut context = BigNumContext::new().unwrap();
+        let key = std::iter::repeat_with(ec_key)
+            .take(10_000)
+            .find(|key| {
+                let ec = key.ec_key().unwrap();
+                let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+                ec.public_key().affine_coordinates_gfp(ec.group(), &mut x, &mut y, &mut context).unwrap();
+                x.num_bytes() < 32 || y.num_bytes() < 32
+            })
+            .unwrap();
+
+        let config = asymmetric_config(JwtAlgorithm::Es256, "jwks-ec", &key);
+        let jwks = JwtKeys::from_config(&config).jwks().unwrap();
+
+        let jwk = &jwks.keys[0];
+        assert_eq!((jwk.kty, jwk.alg, jwk.crv, jwk.kid.as_str()), ("EC", "ES256", Some("P-256"), "default"));
+        for coordinate in [&jwk.x, &jwk.y] {
+            let decoded = base64::decode_config(coordinate.as_ref().unwrap(), base64::URL_SAFE_NO_PAD).unwrap();
+            assert_eq!(decoded.len(), 32);
+        }
+    }
+
+    #[test]
+    fn hmac_jwks_is_empty() {
+        let config = Config { jwt_retired_keys: vec![("old".into(), key_file("jwks-hmac", b"old secret"))], ..Config::for_test() };
+
+        assert!(JwtKeys::from_config(&config).jwks().unwrap().keys.is_empty());
+    }
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn issued(issued_at: Option<u64>) -> RegisteredClaims {
+        RegisteredClaims { issued_at, expiration: Some(NOW + 3600), ..Default::default() }
+    }
+
+    #[test]
+    fn token_older_than_max_age_is_rejected() {
+        let config = Config { max_token_age_hours: Some(24), ..Config::for_test() };
+
+        assert!(!is_token_current(&issued(Some(NOW - 25 * 3600)), &config, NOW));
+        assert!(is_token_current(&issued(Some(NOW - 3600)), &config, NOW));
+    }
+
+    #[test]
+    fn token_without_iat_follows_config() {
+        let accept = Config { max_token_age_hours: Some(24), accept_tokens_without_iat: true, ..Config::for_test() };
+        let reject = Config { max_token_age_hours: Some(24), accept_tokens_without_iat: false, ..Config::for_test() };
+
+        assert!(is_token_current(&issued(None), &accept, NOW));
+        assert!(!is_token_current(&issued(None), &reject, NOW));
+    }
+
+    #[test]
+    fn expired_token_is_rejected() {
+        let claims = RegisteredClaims { issued_at: Some(NOW - 60), expiration: Some(NOW), ..Default::default() };
+
+        assert!(!is_token_current(&claims, &Config::for_test(), NOW));
+    }
+
+    #[test]
+    fn token_from_another_tenant_is_rejected() {
+        let config = Config::for_test();
+        let keys = JwtKeys::from_config(&config);
+        let token = create_jwt_string(7, "main", 0, None, &config.jwt_issuer, NOW as i64 + 3600, &keys).unwrap();
+
+        assert_eq!(verified_claims(&token, "main", &config, &keys, NOW).ok().unwrap().tenant, "main");
+        assert_eq!(verified_claims(&token, "other", &config, &keys, NOW).err().unwrap().http_response().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn redirects_stay_on_allowed_targets() {
+        let allowlist = vec!["/dashboard".to_string(), "allowed.com".to_string()];
+
+        assert_eq!(login_redirect(Some("/dashboard?tab=files"), &allowlist), Some("/dashboard?tab=files"));
+        assert_eq!(login_redirect(Some("https://allowed.com/home"), &allowlist), Some("https://allowed.com/home"));
+        assert_eq!(login_redirect(Some("/dashboardevil"), &allowlist), None);
+        assert_eq!(login_redirect(Some("/dashboard/../admin"), &allowlist), None);
+        assert_eq!(login_redirect(Some("/dashboard/%2e%2E/admin"), &allowlist), None);
+        assert_eq!(login_redirect(Some("/dashboard/./files"), &allowlist), None);
+        assert_eq!(login_redirect(Some("/dashboard/..."), &allowlist), Some("/dashboard/..."));
+        assert_eq!(login_redirect(Some("/dashboard?back=../admin"), &allowlist), Some("/dashboard?back=../admin"));
+        assert_eq!(login_redirect(Some("//evil.com"), &allowlist), None);
+        assert_eq!(login_redirect(Some("/\\evil.com"), &allowlist), None);
+        assert_eq!(login_redirect(Some("https://allowed.com@evil.com"), &allowlist), None);
+        assert_eq!(login_redirect(Some("javascript:alert(1)"), &allowlist), None);
+        assert_eq!(login_redirect(None, &allowlist), None);
+    }
+
+    #[test]
+    fn impersonation_sessions_are_refused() {
+        let owner = Session { impersonator: None };
+        let impersonated = Session { impersonator: Some(1) };
+
+        assert!(owner.not_impersonating("nope").is_ok());
+        assert_eq!(impersonated.not_impersonating("nope").unwrap_err().http_response().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn impersonation_is_audited_as_the_admin() {
+        assert_eq!(Session { impersonator: None }.actor(5), 5);
+        assert_eq!(Session { impersonator: Some(1) }.actor(5), 1);
+    }
+
+    fn user(role: UserRole) -> UserData {
+        UserData {
+            id: 7,
+            tenant: "main".into(),
+            password: String::new(),
+            username: "kawaii".into(),
+            email: "kawaii@example.com".into(),
+            verified: true,
+            role,
+            token_version: 3,
+            password_changed_at: chrono::Utc::now().naive_utc()
+        }
+    }
+
+    /// Token of a new session, bumping the version like `session_cookie` has the database do
+    fn login(config: &Config, keys: &JwtKeys, user: &mut UserData) -> String {
+        if is_single_session(config, user) {
+            user.token_version += 1;
+        }
+        create_jwt_string(user.id, &user.tenant, user.token_version, None, &config.jwt_issuer, NOW as i64 + 3600, keys).unwrap()
+    }
+
+    fn is_accepted(token: &str, user: &UserData, config: &Config, keys: &JwtKeys) -> bool {
+        let claims = verified_claims(token, &user.tenant, config, keys, NOW).ok().unwrap();
+        is_current_version(user, claims.version)
+    }
+
+    #[test]
+    fn stale_token_version_is_rejected() {
+        let user = user(UserRole::User);
+
+        assert!(is_current_version(&user, 3));
+        assert!(!is_current_version(&user, 2));
+        assert!(!is_current_version(&user, 4));
+    }
+
+    #[test]
+    fn second_login_ends_the_first_session_in_single_session_mode() {
+        let config = Config { single_session_roles: vec![UserRole::Admin], ..Config::for_test() };
+        let keys = JwtKeys::from_config(&config);
+
+        let mut admin = user(UserRole::Admin);
+        let first = login(&config, &keys, &mut admin);
+        let second = login(&config, &keys, &mut admin);
+        assert!(!is_accepted(&first, &admin, &config, &keys));
+        assert!(is_accepted(&second, &admin, &config, &keys));
+
+        // Other roles keep every session
+        let mut user = user(UserRole::User);
+        let first = login(&config, &keys, &mut user);
+        let second = login(&config, &keys, &mut user);
+        assert!(is_accepted(&first, &user, &config, &keys));
+        assert!(is_accepted(&second, &user, &config, &keys));
+    }
+}
\ No newline at end of file
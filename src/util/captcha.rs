@@ -0,0 +1,197 @@
+use actix_web::client::Client;
+use futures::future::{FutureExt, LocalBoxFuture, ready};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{CaptchaKind, Config};
+
+/// Checks a captcha token solved by a client
+pub trait CaptchaProvider: Send + Sync {
+    fn verify<'a>(&'a self, token: &'a str, ip: Option<IpAddr>) -> LocalBoxFuture<'a, bool>;
+}
+
+/// hCaptcha and reCAPTCHA share the same siteverify API
+pub struct SiteVerify {
+    url: &'static str,
+    secret: String
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool
+}
+
+impl SiteVerify {
+    pub fn hcaptcha(secret: &str) -> Self {
+        Self { url: "https://hcaptcha.com/siteverify", secret: secret.into() }
+    }
+    pub fn recaptcha(secret: &str) -> Self {
+        Self { url: "https://www.google.com/recaptcha/api/siteverify", secret: secret.into() }
+    }
+}
+
+impl CaptchaProvider for SiteVerify {
+    fn verify<'a>(&'a self, token: &'a str, ip: Option<IpAddr>) -> LocalBoxFuture<'a, bool> {
+        async move {
+            let mut form = vec![("secret", self.secret.clone()), ("response", token.to_string())];
+            if let Some(ip) = ip {
+                form.push(("remoteip", ip.to_string()));
+            }
+
+            let mut response = match Client::default().post(self.url).send_form(&form).await {
+                Ok(response) => response,
+                Err(_) => return false
+            };
+
+            // Treat an unreachable provider as a failed captcha rather than letting logins through
+            match response.json::<SiteVerifyResponse>().await {
+                Ok(body) => body.success,
+                Err(_) => false
+            }
+        }.boxed_local()
+    }
+}
+
+/// Accepts any non-empty token, for development and tests
+pub struct NoopCaptcha;
+
+impl CaptchaProvider for NoopCaptcha {
+    fn verify<'a>(&'a self, token: &'a str, _: Option<IpAddr>) -> LocalBoxFuture<'a, bool> {
+        ready(!token.is_empty()).boxed_local()
+    }
+}
+
+/// IPs with failed logins kept at most, past that the oldest are forgotten first
+const MAX_TRACKED_IPS: usize = 10_000;
+
+/// Failed logins since the window started for an IP
+struct Failures {
+    count: u32,
+    since: Instant
+}
+
+/// Asks for a captcha once an IP has failed to log in too many times within the window
+pub struct LoginCaptcha {
+    threshold: u32,
+    window: Duration,
+    provider: Box<dyn CaptchaProvider>,
+    failures: Mutex<HashMap<IpAddr, Failures>>
+}
+
+impl LoginCaptcha {
+    /// Build from config, `None` when login captchas are disabled
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.captcha_threshold == 0 {
+            return None;
+        }
+
+        let provider: Box<dyn CaptchaProvider> = match config.captcha_provider {
+            CaptchaKind::HCaptcha => Box::new(SiteVerify::hcaptcha(&config.captcha_secret)),
+            CaptchaKind::ReCaptcha => Box::new(SiteVerify::recaptcha(&config.captcha_secret)),
+            CaptchaKind::Noop => Box::new(NoopCaptcha)
+        };
+
+        Some(Self {
+            threshold: config.captcha_threshold,
+            window: Duration::from_secs(config.captcha_window_minutes * 60),
+            provider,
+            failures: Mutex::new(HashMap::new())
+        })
+    }
+    /// Whether logins from this IP have to solve a captcha
+    pub fn required(&self, ip: IpAddr) -> bool {
+        self.required_at(ip, Instant::now())
+    }
+    pub fn record_failure(&self, ip: IpAddr) {
+        self.record_failure_at(ip, Instant::now())
+    }
+    fn required_at(&self, ip: IpAddr, now: Instant) -> bool {
+        self.failures.lock().unwrap().get(&ip)
+            .is_some_and(|failures| now.duration_since(failures.since) < self.window && failures.count >= self.threshold)
+    }
+    fn record_failure_at(&self, ip: IpAddr, now: Instant) {
+        let mut failures = self.failures.lock().unwrap();
+
+        if failures.len() >= MAX_TRACKED_IPS && !failures.contains_key(&ip) {
+            failures.retain(|_, failures| now.duration_since(failures.since) < self.window);
+
+            // Everything is recent, so make room by dropping the window that ends soonest
+            if failures.len() >= MAX_TRACKED_IPS {
+                let oldest = failures.iter().min_by_key(|(_, failures)| failures.since).map(|(ip, _)| *ip);
+                if let Some(oldest) = oldest {
+                    failures.remove(&oldest);
+                }
+            }
+        }
+
+        let entry = failures.entry(ip).or_insert(Failures { count: 0, since: now });
+        if now.duration_since(entry.since) >= self.window {
+            *entry = Failures { count: 0, since: now };
+        }
+        entry.count += 1;
+    }
+    pub fn reset(&self, ip: IpAddr) {
+        self.failures.lock().unwrap().remove(&ip);
+    }
+    pub async fn verify(&self, token: &str, ip: IpAddr) -> bool {
+        self.provider.verify(token, Some(ip)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captcha(threshold: u32) -> LoginCaptcha {
+        LoginCaptcha::from_config(&Config { captcha_threshold: threshold, ..Config::for_test() }).unwrap()
+    }
+
+    #[test]
+    fn required_after_threshold_failures() {
+        let captcha = captcha(2);
+        let (ip, other) = ("203.0.113.9".parse().unwrap(), "198.51.100.1".parse().unwrap());
+        let now = Instant::now();
+
+        captcha.record_failure_at(ip, now);
+        assert!(!captcha.required_at(ip, now));
+        captcha.record_failure_at(ip, now);
+        assert!(captcha.required_at(ip, now));
+        assert!(!captcha.required_at(other, now));
+
+        captcha.reset(ip);
+        assert!(!captcha.required_at(ip, now));
+    }
+
+    #[test]
+    fn failures_expire_after_the_window() {
+        let captcha = captcha(2);
+        let ip = "203.0.113.9".parse().unwrap();
+        let now = Instant::now();
+
+        captcha.record_failure_at(ip, now);
+        captcha.record_failure_at(ip, now);
+        assert!(!captcha.required_at(ip, now + captcha.window));
+
+        // A failure after the window starts counting again
+        captcha.record_failure_at(ip, now + captcha.window);
+        assert!(!captcha.required_at(ip, now + captcha.window));
+    }
+
+    #[test]
+    fn tracked_ips_are_capped() {
+        let captcha = captcha(1);
+        let now = Instant::now();
+
+        for i in 0..MAX_TRACKED_IPS as u32 {
+            captcha.record_failure_at(IpAddr::from(i.to_be_bytes()), now + Duration::from_millis(i as u64));
+        }
+        captcha.record_failure_at("203.0.113.9".parse().unwrap(), now + Duration::from_secs(60));
+
+        assert_eq!(captcha.failures.lock().unwrap().len(), MAX_TRACKED_IPS);
+        assert!(!captcha.required_at(IpAddr::from(0u32.to_be_bytes()), now));
+        assert!(captcha.required_at("203.0.113.9".parse().unwrap(), now + Duration::from_secs(60)));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,117 @@
+use actix_web::dev::RequestHead;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::config::{ClientIpHeader, Config};
+
+/// Client address, taken from the configured forwarding headers when the request came through a trusted proxy.
+/// Takes the head so both handlers and middleware can call it
+pub fn client_ip(head: &RequestHead, config: &Config) -> Option<IpAddr> {
+    let peer = head.peer_addr?.ip();
+    if !config.trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    let header = |name: &str| head.headers().get(name).and_then(|value| value.to_str().ok());
+    let client = config.client_ip_headers.iter().find_map(|kind| match kind {
+        // The proxy appends the address it saw, so the last entry it didn't add itself is the client
+        ClientIpHeader::XForwardedFor => header("X-Forwarded-For").and_then(|list| list.rsplit(',')
+            .filter_map(|entry| entry.trim().parse::<IpAddr>().ok())
+            .find(|ip| !config.trusted_proxies.contains(ip))),
+        ClientIpHeader::XRealIp => header("X-Real-IP").and_then(|ip| ip.trim().parse().ok())
+    });
+
+    Some(client.unwrap_or(peer))
+}
+
+/// Network of an address, enough to tell clients apart in logs without identifying anyone
+pub fn truncate(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        },
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            IpAddr::V6(Ipv6Addr::new(segments[0], segments[1], segments[2], 0, 0, 0, 0, 0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn config() -> Config {
+        Config { trusted_proxies: vec!["10.0.0.1".parse().unwrap()], ..Config::for_test() }
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let req = TestRequest::default()
+            .peer_addr("203.0.113.9:4000".parse().unwrap())
+            .header("X-Forwarded-For", "198.51.100.1")
+            .to_http_request();
+
+        assert_eq!(client_ip(req.head(), &config()), Some("203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn trusted_proxy_forwards_the_client() {
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .header("X-Forwarded-For", "198.51.100.1, 203.0.113.9, 10.0.0.1")
+            .to_http_request();
+
+        assert_eq!(client_ip(req.head(), &config()), Some("203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn trusted_proxy_without_header_is_the_client() {
+        let req = TestRequest::default().peer_addr("10.0.0.1:4000".parse().unwrap()).to_http_request();
+
+        assert_eq!(client_ip(req.head(), &config()), Some("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn real_ip_is_used_without_forwarded_for() {
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .header("X-Real-IP", "203.0.113.9")
+            .to_http_request();
+
+        assert_eq!(client_ip(req.head(), &config()), Some("203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn header_precedence_follows_config() {
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .header("X-Forwarded-For", "198.51.100.1")
+            .header("X-Real-IP", "203.0.113.9")
+            .to_http_request();
+
+        let real_ip_first = Config { client_ip_headers: vec![ClientIpHeader::XRealIp, ClientIpHeader::XForwardedFor], ..config() };
+
+        assert_eq!(client_ip(req.head(), &config()), Some("198.51.100.1".parse().unwrap()));
+        assert_eq!(client_ip(req.head(), &real_ip_first), Some("203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn unconfigured_header_is_ignored() {
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .header("X-Real-IP", "203.0.113.9")
+            .to_http_request();
+
+        let forwarded_only = Config { client_ip_headers: vec![ClientIpHeader::XForwardedFor], ..config() };
+
+        assert_eq!(client_ip(req.head(), &forwarded_only), Some("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn truncate_zeroes_the_host_part() {
+        assert_eq!(truncate("203.0.113.9".parse().unwrap()), "203.0.113.0".parse::<IpAddr>().unwrap());
+        assert_eq!(truncate("2001:db8:85a3:1234:5678:8a2e:370:7334".parse().unwrap()), "2001:db8:85a3::".parse::<IpAddr>().unwrap());
+    }
+}
\ No newline at end of file
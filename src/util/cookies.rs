@@ -0,0 +1,146 @@
+use actix_web::{HttpMessage, HttpRequest, http::Cookie};
+#[cfg(test)]
+use hmac::{Hmac, Mac, NewMac};
+#[cfg(test)]
+use rand::Rng;
+#[cfg(test)]
+use sha2::Sha256;
+use time::OffsetDateTime;
+
+use crate::config::Config;
+
+/// Cookie holding a signed JWT token, pass an empty token and expiry 0 to clear it.
+/// Every cookie is built here so name, path and flags stay the same everywhere
+pub fn auth_cookie(config: &Config, jwt: String, expire_time: i64) -> Cookie<'static> {
+    Cookie::build(config.cookie_name.clone(), jwt)
+        .secure(config.secure_cookies)
+        .http_only(true)
+        .path(config.cookie_path.clone())
+        .same_site(config.cookie_same_site)
+        .expires(OffsetDateTime::from_unix_timestamp(expire_time))
+        .finish()
+}
+
+/// JWT token sent in the auth cookie
+pub fn auth_token(req: &HttpRequest, config: &Config) -> Option<String> {
+    req.cookie(&config.cookie_name).map(|cookie| cookie.value().to_string())
+}
+
+/// Key for cookies that aren't JWTs but still must not be forged, derived from the JWT secret
+#[cfg(test)]
+pub struct CookieKey(Hmac<Sha256>);
+
+#[cfg(test)]
+impl CookieKey {
+    pub fn from_config(config: &Config) -> Self {
+        // Without a JWT secret the key is random like the JWT key, so signed cookies end with the process
+        let secret = match &config.jwt_secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => rand::thread_rng().gen::<[u8; 32]>().to_vec()
+        };
+
+        // Derived instead of reused so a cookie signature can never pass as a JWT signature
+        let mut derive = Hmac::<Sha256>::new_varkey(&secret).expect("Could not derive cookie key");
+        derive.update(b"kawaii.sh cookie signing");
+        let key = derive.finalize().into_bytes();
+
+        Self(Hmac::new_varkey(&key).expect("Could not derive cookie key"))
+    }
+    /// MAC over the name as well, so a value signed for one cookie is rejected as another
+    fn mac(&self, name: &str, value: &str) -> Hmac<Sha256> {
+        let mut mac = self.0.clone();
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+}
+
+/// Cookie whose value is followed by its signature, read it back with [`verify_signed_cookie`]
+#[cfg(test)]
+pub fn signed_cookie(key: &CookieKey, config: &Config, name: &str, value: &str) -> Cookie<'static> {
+    let signature = base64::encode_config(key.mac(name, value).finalize().into_bytes(), base64::URL_SAFE_NO_PAD);
+
+    Cookie::build(name.to_string(), format!("{}.{}", value, signature))
+        .secure(config.secure_cookies)
+        .http_only(true)
+        .path(config.cookie_path.clone())
+        .finish()
+}
+
+/// Value of a cookie made by [`signed_cookie`], `None` when it's missing or was tampered with
+#[cfg(test)]
+pub fn verify_signed_cookie(key: &CookieKey, cookie: &Cookie) -> Option<String> {
+    let (value, signature) = cookie.value().rsplit_once('.')?;
+    let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+
+    // Constant time comparison
+    key.mac(cookie.name(), value).verify(&signature).ok()?;
+    Some(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{cookie::SameSite, test::TestRequest};
+
+    #[test]
+    fn custom_cookie_name_and_path_round_trip() {
+        let config = Config { cookie_name: "kawaii-session".into(), cookie_path: "/api".into(), ..Config::for_test() };
+
+        let cookie = auth_cookie(&config, "token".into(), 0);
+        assert_eq!(cookie.name(), "kawaii-session");
+        assert_eq!(cookie.path(), Some("/api"));
+
+        let req = TestRequest::default().cookie(cookie).to_http_request();
+        assert_eq!(auth_token(&req, &config).as_deref(), Some("token"));
+
+        // The default name isn't read any more
+        let req = TestRequest::default().cookie(Cookie::new("auth-token", "token")).to_http_request();
+        assert_eq!(auth_token(&req, &config), None);
+    }
+
+    #[test]
+    fn auth_cookie_is_same_site() {
+        let cookie = auth_cookie(&Config::for_test(), "token".into(), 0);
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+
+        let strict = Config { cookie_same_site: SameSite::Strict, ..Config::for_test() };
+        assert_eq!(auth_cookie(&strict, "token".into(), 0).same_site(), Some(SameSite::Strict));
+    }
+
+    #[test]
+    fn signed_cookie_round_trip() {
+        let config = Config::for_test();
+        let key = CookieKey::from_config(&config);
+
+        let cookie = signed_cookie(&key, &config, "theme", "dark.mode");
+
+        assert_eq!(verify_signed_cookie(&key, &cookie).as_deref(), Some("dark.mode"));
+    }
+
+    #[test]
+    fn tampered_cookie_is_rejected() {
+        let config = Config::for_test();
+        let key = CookieKey::from_config(&config);
+        let cookie = signed_cookie(&key, &config, "theme", "dark");
+        let (_, signature) = cookie.value().rsplit_once('.').unwrap();
+
+        let tampered = Cookie::new("theme", format!("light.{}", signature));
+        let renamed = Cookie::new("role", cookie.value().to_string());
+        let unsigned = Cookie::new("theme", "dark");
+
+        assert_eq!(verify_signed_cookie(&key, &tampered), None);
+        assert_eq!(verify_signed_cookie(&key, &renamed), None);
+        assert_eq!(verify_signed_cookie(&key, &unsigned), None);
+    }
+
+    #[test]
+    fn cookie_from_another_secret_is_rejected() {
+        let config = Config::for_test();
+        let other = Config { jwt_secret: Some("another secret".into()), ..Config::for_test() };
+        let cookie = signed_cookie(&CookieKey::from_config(&other), &other, "theme", "dark");
+
+        assert_eq!(verify_signed_cookie(&CookieKey::from_config(&config), &cookie), None);
+    }
+}
\ No newline at end of file
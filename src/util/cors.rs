@@ -0,0 +1,246 @@
+use actix_web::{dev::{Service, ServiceRequest, ServiceResponse, Transform}, http::{header, HeaderMap, HeaderValue, Method}, Error, HttpResponse};
+use futures::future::{Either, LocalBoxFuture, Ready, ok};
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::config::Config;
+
+/// Methods the route with a resource name accepts
+pub type RouteMethods = fn(&str) -> Option<&'static [Method]>;
+
+/// CORS headers for the origins in the config
+struct CorsPolicy {
+    origins: Vec<String>,
+    route_methods: RouteMethods,
+    allowed_headers: String,
+    exposed_headers: String,
+    max_age: u32
+}
+
+impl CorsPolicy {
+    /// The request's origin if it's allowed to make cross origin requests
+    fn allowed_origin(&self, headers: &HeaderMap) -> Option<HeaderValue> {
+        let origin = headers.get(header::ORIGIN)?;
+        let allowed = origin.to_str().is_ok_and(|origin| self.origins.iter().any(|allowed| allowed == origin));
+
+        allowed.then(|| origin.clone())
+    }
+}
+
+/// Middleware answering CORS preflights with the methods of the route they're for, and tagging
+/// responses for the allowed origins. It wraps the whole app so browsers can also read the
+/// responses of the rate limit and outage middleware
+pub struct Cors {
+    policy: Rc<CorsPolicy>
+}
+
+impl Cors {
+    pub fn new(config: &Config, route_methods: RouteMethods) -> Self {
+        Self {
+            policy: Rc::new(CorsPolicy {
+                origins: config.cors_allowed_origins.clone(),
+                route_methods,
+                allowed_headers: config.cors_allowed_headers.join(", "),
+                exposed_headers: config.cors_exposed_headers.join(", "),
+                max_age: config.cors_max_age
+            })
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Cors
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CorsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CorsMiddleware { service, policy: self.policy.clone() })
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: S,
+    policy: Rc<CorsPolicy>
+}
+
+impl<S, B> Service for CorsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<LocalBoxFuture<'static, Result<Self::Response, Self::Error>>, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        // Same origin requests and unknown origins get no CORS headers, so browsers block the latter
+        let origin = match self.policy.allowed_origin(req.headers()) {
+            Some(origin) => origin,
+            None => return Either::Left(Box::pin(self.service.call(req)))
+        };
+
+        let preflight = req.method() == Method::OPTIONS && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if preflight {
+            // Preflights for paths without a route get the 404 without CORS headers
+            let methods = match req.resource_map().match_name(req.path()).and_then(self.policy.route_methods) {
+                Some(methods) => methods.iter().map(Method::as_str).collect::<Vec<_>>().join(", "),
+                None => return Either::Left(Box::pin(self.service.call(req)))
+            };
+
+            let mut response = HttpResponse::NoContent();
+            response
+                .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true")
+                .header(header::ACCESS_CONTROL_ALLOW_METHODS, methods)
+                .header(header::VARY, "Origin");
+
+            if !self.policy.allowed_headers.is_empty() {
+                response.header(header::ACCESS_CONTROL_ALLOW_HEADERS, self.policy.allowed_headers.as_str());
+            }
+
+            // Lets browsers skip the preflight for later requests
+            if self.policy.max_age > 0 {
+                response.header(header::ACCESS_CONTROL_MAX_AGE, self.policy.max_age.to_string());
+            }
+
+            return Either::Right(ok(req.into_response(response.finish().into_body())));
+        }
+
+        let policy = self.policy.clone();
+        let response = self.service.call(req);
+
+        Either::Left(Box::pin(async move {
+            let mut response = response.await?;
+            let headers = response.headers_mut();
+
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+            headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+
+            if let Ok(exposed) = HeaderValue::from_str(&policy.exposed_headers) {
+                if !exposed.is_empty() {
+                    headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, exposed);
+                }
+            }
+
+            Ok(response)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{state::State, util::rate_limit::{limit_requests, MemoryRateLimiter}};
+    use actix_web::{http::StatusCode, test, web, App};
+
+    const ITEM_METHODS: &[Method] = &[Method::GET, Method::DELETE];
+
+    fn item_methods(name: &str) -> Option<&'static [Method]> {
+        (name == "item").then_some(ITEM_METHODS)
+    }
+
+    fn config() -> Config {
+        Config {
+            cors_allowed_origins: vec!["https://app.example.com".into()],
+            cors_allowed_headers: vec!["Content-Type".into()],
+            cors_exposed_headers: vec!["X-Request-Id".into(), "Retry-After".into()],
+            cors_max_age: 600,
+            ..Config::for_test()
+        }
+    }
+
+    /// One route behind the rate limiter, wrapped like the API scope
+    macro_rules! app {
+        ($state:expr) => {
+            test::init_service(App::new()
+                .data($state)
+                .wrap(Cors::new(&config(), item_methods))
+                .service(web::scope("/api/")
+                    .wrap_fn(limit_requests)
+                    .service(web::resource("item").name("item")
+                        .route(web::get().to(HttpResponse::Ok))
+                        .route(web::delete().to(HttpResponse::Ok)))))
+        };
+    }
+
+    fn preflight(origin: &str, path: &str) -> test::TestRequest {
+        test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri(path)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+    }
+
+    #[actix_rt::test]
+    async fn preflight_lists_the_methods_of_the_route() {
+        let mut app = app!(State::for_test(config())).await;
+
+        let response = test::call_service(&mut app, preflight("https://app.example.com", "/api/item").to_request()).await;
+        let headers = response.headers();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://app.example.com");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_METHODS).unwrap(), "GET, DELETE");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap(), "Content-Type");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+    }
+
+    #[actix_rt::test]
+    async fn responses_expose_the_configured_headers() {
+        let mut app = app!(State::for_test(config())).await;
+
+        let req = test::TestRequest::get().uri("/api/item").header(header::ORIGIN, "https://app.example.com").to_request();
+        let response = test::call_service(&mut app, req).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://app.example.com");
+        assert_eq!(response.headers().get(header::ACCESS_CONTROL_EXPOSE_HEADERS).unwrap(), "X-Request-Id, Retry-After");
+    }
+
+    #[actix_rt::test]
+    async fn unknown_origins_and_paths_get_no_cors_headers() {
+        let mut app = app!(State::for_test(config())).await;
+
+        let unknown_origin = test::call_service(&mut app, preflight("https://evil.example.com", "/api/item").to_request()).await;
+        assert!(unknown_origin.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        assert_ne!(unknown_origin.status(), StatusCode::NO_CONTENT);
+
+        let unknown_path = test::call_service(&mut app, preflight("https://app.example.com", "/api/nothing").to_request()).await;
+        assert!(unknown_path.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        assert_eq!(unknown_path.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn rate_limited_responses_can_be_read_cross_origin() {
+        let state = State { rate_limiter: Some(Box::new(MemoryRateLimiter::new(1, 1))), ..State::for_test(config()) };
+        let mut app = app!(state).await;
+
+        let request = || test::TestRequest::get()
+            .uri("/api/item")
+            .header(header::ORIGIN, "https://app.example.com")
+            .peer_addr("203.0.113.9:4000".parse().unwrap())
+            .to_request();
+
+        assert_eq!(test::call_service(&mut app, request()).await.status(), StatusCode::OK);
+
+        let limited = test::call_service(&mut app, request()).await;
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://app.example.com");
+        assert!(limited.headers().contains_key(header::RETRY_AFTER));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,97 @@
+use actix_web::{dev::Payload, http::StatusCode, web::Bytes, Error, FromRequest, HttpMessage, HttpRequest};
+use futures::future::{FutureExt, LocalBoxFuture};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+
+use crate::models::MessageResponse;
+
+/// Body sent either as JSON or as an HTML form (`application/x-www-form-urlencoded`)
+pub struct JsonOrForm<T>(pub T);
+
+impl<T: DeserializeOwned + 'static> FromRequest for JsonOrForm<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        // Without parameters like charset
+        let content_type = req.content_type().to_lowercase();
+        let body = Bytes::from_request(req, payload);
+
+        async move {
+            let body = body.await?;
+
+            let parsed = match content_type.as_str() {
+                "application/json" => serde_json::from_slice(&body).ok(),
+                "application/x-www-form-urlencoded" => serde_urlencoded::from_bytes(&body).ok(),
+                _ => return Err(MessageResponse::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Send the body as JSON or form data")
+                    .with_error_code("UNSUPPORTED_MEDIA_TYPE")
+                    .into())
+            };
+
+            // Same response as a JSON body that failed to deserialize
+            parsed.map(JsonOrForm).ok_or_else(|| MessageResponse::bad_request().into())
+        }.boxed_local()
+    }
+}
+
+/// Deserialize a string with surrounding whitespace removed and in lowercase
+pub fn trimmed_lowercase<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(String::deserialize(deserializer)?.trim().to_lowercase())
+}
+
+/// Deserialize a string, anything else becomes `None` so validation can name the field
+pub fn string_only<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(value) => Ok(Some(value)),
+        _ => Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::auth::BasicAuthForm;
+    use actix_web::{http::header::CONTENT_TYPE, test, web, App, HttpResponse};
+
+    async fn echo(JsonOrForm(form): JsonOrForm<BasicAuthForm>) -> HttpResponse {
+        HttpResponse::Ok().json(serde_json::json!({ "email": form.email, "password": form.password, "next": form.next }))
+    }
+
+    #[actix_rt::test]
+    async fn json_and_form_bodies_parse_the_same() {
+        let mut app = test::init_service(App::new().route("/login", web::post().to(echo))).await;
+
+        let json = test::TestRequest::post()
+            .uri("/login")
+            .set_json(&serde_json::json!({ "email": "kawaii@example.com", "password": "hunter22", "next": "/dashboard" }))
+            .to_request();
+        let form = test::TestRequest::post()
+            .uri("/login")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded; charset=utf-8")
+            .set_payload("email=kawaii%40example.com&password=hunter22&next=%2Fdashboard")
+            .to_request();
+
+        let json: serde_json::Value = test::read_body_json(test::call_service(&mut app, json).await).await;
+        let form: serde_json::Value = test::read_body_json(test::call_service(&mut app, form).await).await;
+
+        assert_eq!(json["email"], "kawaii@example.com");
+        assert_eq!(json["next"], "/dashboard");
+        assert_eq!(json, form);
+    }
+
+    #[actix_rt::test]
+    async fn other_content_types_are_unsupported() {
+        let mut app = test::init_service(App::new().route("/login", web::post().to(echo))).await;
+
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .header(CONTENT_TYPE, "text/plain")
+            .set_payload("email=kawaii%40example.com&password=hunter22")
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error_code"], "UNSUPPORTED_MEDIA_TYPE");
+    }
+}
\ No newline at end of file
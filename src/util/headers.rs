@@ -0,0 +1,41 @@
+use actix_web::middleware::DefaultHeaders;
+
+use crate::config::Config;
+
+/// Middleware adding the configured security headers to every response.
+/// Headers a handler already set are left alone
+pub fn security_headers(config: &Config) -> DefaultHeaders {
+    config.security_headers.iter()
+        .fold(DefaultHeaders::new(), |headers, (name, value)| headers.header(name.as_str(), value.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MessageResponse;
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+
+    #[actix_rt::test]
+    async fn configured_headers_are_on_every_response() {
+        let config = Config {
+            security_headers: vec![("X-Frame-Options".into(), "DENY".into()), ("Referrer-Policy".into(), "no-referrer".into())],
+            ..Config::for_test()
+        };
+        let mut app = test::init_service(App::new()
+            .wrap(security_headers(&config))
+            .route("/ok", web::get().to(HttpResponse::Ok))
+            .route("/framed", web::get().to(|| HttpResponse::Ok().header("X-Frame-Options", "SAMEORIGIN").finish()))
+            .default_service(web::route().to(|| MessageResponse::not_found().http_response()))).await;
+
+        for (path, status) in [("/ok", StatusCode::OK), ("/missing", StatusCode::NOT_FOUND)] {
+            let response = test::call_service(&mut app, test::TestRequest::get().uri(path).to_request()).await;
+
+            assert_eq!(response.status(), status);
+            assert_eq!(response.headers().get("X-Frame-Options").unwrap(), "DENY");
+            assert_eq!(response.headers().get("Referrer-Policy").unwrap(), "no-referrer");
+        }
+
+        let framed = test::call_service(&mut app, test::TestRequest::get().uri("/framed").to_request()).await;
+        assert_eq!(framed.headers().get("X-Frame-Options").unwrap(), "SAMEORIGIN");
+    }
+}
\ No newline at end of file
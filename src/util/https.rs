@@ -0,0 +1,133 @@
+use actix_web::{dev::{Service, ServiceRequest, ServiceResponse, Transform}, http::header::{HOST, LOCATION}, Error, HttpResponse};
+use futures::future::{Either, Ready, ok};
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+/// Middleware redirecting plain HTTP requests to their HTTPS equivalent
+pub struct ForceHttps {
+    trusted_proxies: Rc<Vec<IpAddr>>
+}
+
+impl ForceHttps {
+    /// `X-Forwarded-Proto` is only believed when sent by one of the trusted proxies
+    pub fn new(trusted_proxies: &[IpAddr]) -> Self {
+        Self { trusted_proxies: Rc::new(trusted_proxies.to_vec()) }
+    }
+}
+
+impl<S, B> Transform<S> for ForceHttps
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ForceHttpsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ForceHttpsMiddleware { service, trusted_proxies: self.trusted_proxies.clone() })
+    }
+}
+
+pub struct ForceHttpsMiddleware<S> {
+    service: S,
+    trusted_proxies: Rc<Vec<IpAddr>>
+}
+
+impl<S> ForceHttpsMiddleware<S> {
+    /// Whether the client reached us over TLS, either directly or through a trusted proxy
+    fn is_secure(&self, req: &ServiceRequest) -> bool {
+        let from_proxy = req.peer_addr().is_some_and(|addr| self.trusted_proxies.contains(&addr.ip()));
+        if from_proxy {
+            if let Some(proto) = req.headers().get("X-Forwarded-Proto").and_then(|proto| proto.to_str().ok()) {
+                return proto.eq_ignore_ascii_case("https");
+            }
+        }
+
+        req.app_config().secure()
+    }
+}
+
+impl<S, B> Service for ForceHttpsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if self.is_secure(&req) {
+            return Either::Left(self.service.call(req));
+        }
+
+        // Without a host there is nowhere to redirect to
+        let host = match req.headers().get(HOST).and_then(|host| host.to_str().ok()) {
+            Some(host) => host.to_string(),
+            None => return Either::Left(self.service.call(req))
+        };
+
+        let path = req.uri().path_and_query().map(|path| path.as_str()).unwrap_or("/");
+        let location = format!("https://{}{}", host, path);
+
+        Either::Right(ok(req.into_response(
+            HttpResponse::MovedPermanently()
+                .header(LOCATION, location)
+                .finish()
+                .into_body()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+
+    const PROXY: &str = "10.0.0.1:4000";
+
+    macro_rules! app {
+        () => {
+            test::init_service(App::new()
+                .wrap(ForceHttps::new(&[PROXY.parse::<std::net::SocketAddr>().unwrap().ip()]))
+                .route("/page", web::get().to(HttpResponse::Ok)))
+        };
+    }
+
+    #[actix_rt::test]
+    async fn plain_http_is_redirected() {
+        let mut app = app!().await;
+
+        let req = test::TestRequest::get().uri("/page?tab=1").header(HOST, "kawaii.sh").peer_addr("203.0.113.9:4000".parse().unwrap()).to_request();
+        let response = test::call_service(&mut app, req).await;
+
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers().get(LOCATION).unwrap(), "https://kawaii.sh/page?tab=1");
+    }
+
+    #[actix_rt::test]
+    async fn forwarded_proto_is_believed_from_trusted_proxies_only() {
+        let mut app = app!().await;
+        let forwarded = |peer: &str| test::TestRequest::get()
+            .uri("/page")
+            .header(HOST, "kawaii.sh")
+            .header("X-Forwarded-Proto", "https")
+            .peer_addr(peer.parse().unwrap())
+            .to_request();
+
+        assert_eq!(test::call_service(&mut app, forwarded(PROXY)).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&mut app, forwarded("203.0.113.9:4000")).await.status(), StatusCode::MOVED_PERMANENTLY);
+    }
+}
\ No newline at end of file
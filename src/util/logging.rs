@@ -0,0 +1,216 @@
+use actix_web::{dev::{Service, ServiceRequest, ServiceResponse}, http::{HeaderName, HeaderValue}, web::Data, Error};
+use futures::Future;
+use std::time::Instant;
+use tracing::{field, Instrument};
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{Config, LogFormat};
+use crate::state::State;
+use crate::util::client_ip::{client_ip, truncate};
+
+/// Header a request id is read from and returned in
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Query parameters whose values never end up in logs
+const REDACTED_PARAMS: &[&str] = &["password", "token", "secret", "code"];
+
+/// Install the global log subscriber, the level is taken from `RUST_LOG` and defaults to info
+pub fn init(config: &Config) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match config.log_format {
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(false).init(),
+        LogFormat::Text => builder.init(),
+    }
+}
+
+/// Log output of a test, written by the subscriber from [`capture_logs`]
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl CapturedLogs {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+#[cfg(test)]
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Capture what this thread logs until the guard is dropped
+#[cfg(test)]
+pub fn capture_logs() -> (CapturedLogs, tracing::subscriber::DefaultGuard) {
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt().with_ansi(false).with_writer(move || writer.clone()).finish();
+
+    (logs, tracing::subscriber::set_default(subscriber))
+}
+
+/// Middleware function running every request in a span with its id, route and user.
+/// The id is returned in the `X-Request-Id` response header
+pub fn log_request<S, B>(req: ServiceRequest, srv: &mut S) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let state = req.app_data::<Data<State>>();
+
+    // Keep the id a gateway in front of us assigned, so logs correlate across services
+    let from_proxy = match (&state, req.peer_addr()) {
+        (Some(state), Some(addr)) => state.config.trusted_proxies.contains(&addr.ip()),
+        _ => false
+    };
+    let request_id = from_proxy.then(|| forwarded_request_id(&req)).flatten()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let logged_ip = state.filter(|state| state.config.log_client_ips).and_then(|state| {
+        let ip = client_ip(req.head(), &state.config)?;
+        Some(if state.config.log_truncate_ips { truncate(ip) } else { ip })
+    });
+    let client_ip = match logged_ip {
+        Some(ip) => ip.to_string(),
+        None => "redacted".to_string()
+    };
+
+    // The user id is filled in once the request is authenticated
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+        query = %redact_query(req.query_string()),
+        client_ip = %client_ip,
+        user_id = field::Empty,
+    );
+
+    let start = Instant::now();
+    let response = srv.call(req);
+
+    async move {
+        let mut response = response.await;
+        let elapsed_ms = start.elapsed().as_millis() as u64;
+
+        if let (Ok(response), Ok(value)) = (&mut response, HeaderValue::from_str(&request_id)) {
+            response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+
+        match &response {
+            Ok(response) if response.status().is_server_error() => tracing::error!(status = response.status().as_u16(), elapsed_ms, "request failed"),
+            Ok(response) => tracing::info!(status = response.status().as_u16(), elapsed_ms, "request finished"),
+            Err(error) => tracing::error!(error = %error, elapsed_ms, "request failed"),
+        }
+
+        response
+    }.instrument(span)
+}
+
+/// Request id sent by a proxy, from `X-Request-Id` or the trace id of a W3C `traceparent`
+fn forwarded_request_id(req: &ServiceRequest) -> Option<String> {
+    let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok());
+
+    // Anything else could be used to inject into log lines
+    let valid = |id: &str| !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+
+    if let Some(id) = header(REQUEST_ID_HEADER).filter(|id| valid(id)) {
+        return Some(id.to_string());
+    }
+
+    // version-traceid-parentid-flags
+    let trace_id = header("traceparent")?.split('-').nth(1)?;
+    if trace_id.len() == 32 && trace_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Some(trace_id.to_string());
+    }
+
+    None
+}
+
+/// Query string with the values of sensitive parameters replaced
+fn redact_query(query: &str) -> String {
+    query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let name = pair.split('=').next().unwrap_or_default();
+            let sensitive = REDACTED_PARAMS.iter().any(|param| name.to_lowercase().contains(param));
+
+            if sensitive {
+                format!("{}=[redacted]", name)
+            } else {
+                pair.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    const PROXY: &str = "10.0.0.1:4000";
+
+    async fn request_id(peer: &str, headers: &[(&str, &str)]) -> String {
+        let config = Config { trusted_proxies: vec!["10.0.0.1".parse().unwrap()], ..Config::for_test() };
+        let mut app = test::init_service(App::new()
+            .data(State::for_test(config))
+            .wrap_fn(log_request)
+            .route("/", web::get().to(HttpResponse::Ok))).await;
+
+        let req = headers.iter()
+            .fold(test::TestRequest::get().uri("/").peer_addr(peer.parse().unwrap()), |req, (name, value)| req.header(*name, *value))
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+
+        response.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap().to_string()
+    }
+
+    fn is_generated(id: &str) -> bool {
+        uuid::Uuid::parse_str(id).is_ok()
+    }
+
+    #[actix_rt::test]
+    async fn trusted_proxy_ids_are_reused() {
+        assert_eq!(request_id(PROXY, &[("X-Request-Id", "gw-123")]).await, "gw-123");
+        assert_eq!(request_id(PROXY, &[("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")]).await, "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+
+    #[actix_rt::test]
+    async fn other_ids_are_replaced() {
+        // From an untrusted peer
+        let id = request_id("203.0.113.9:4000", &[("X-Request-Id", "gw-123")]).await;
+        assert!(is_generated(&id), "{}", id);
+
+        // Could be used to inject into log lines, or isn't a trace id
+        for (name, value) in [("X-Request-Id", "gw 123 status=200"), ("X-Request-Id", "gw\"123"), ("traceparent", "00-not-a-trace-01")] {
+            let id = request_id(PROXY, &[(name, value)]).await;
+            assert!(is_generated(&id), "{} {}", value, id);
+        }
+
+        assert!(is_generated(&request_id(PROXY, &[]).await));
+    }
+
+    #[test]
+    fn sensitive_query_values_are_redacted() {
+        assert_eq!(redact_query("password=hunter22&page=2"), "password=[redacted]&page=2");
+        assert_eq!(redact_query("api_token=abc&Client_Secret=x&code=123"), "api_token=[redacted]&Client_Secret=[redacted]&code=[redacted]");
+        assert_eq!(redact_query("token"), "token=[redacted]");
+    }
+
+    #[test]
+    fn other_query_values_are_kept() {
+        assert_eq!(redact_query("page=2&limit=25&next=%2Fdashboard"), "page=2&limit=25&next=%2Fdashboard");
+        assert_eq!(redact_query("&&page=2&"), "page=2");
+        assert_eq!(redact_query(""), "");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,199 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// Accounts tracked at most, past that the ones that logged in least recently are forgotten first
+const MAX_TRACKED_ACCOUNTS: usize = 10_000;
+
+/// How long a mailed verification code can be used, a new one is sent after that
+const CODE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Default)]
+struct Account {
+    /// Last successful login from each IP
+    ips: HashMap<IpAddr, Instant>,
+    /// Set once too many IPs logged in, until the owner verifies
+    flagged: bool,
+    /// Code mailed to the owner and when it was issued
+    code: Option<(String, Instant)>
+}
+
+impl Account {
+    fn last_login(&self) -> Option<Instant> {
+        self.ips.values().max().copied()
+    }
+}
+
+/// Flags accounts that successfully log in from more distinct IPs within the window than allowed,
+/// which points to shared or stuffed credentials
+pub struct LoginIps {
+    threshold: usize,
+    window: Duration,
+    accounts: Mutex<HashMap<(String, i32), Account>>
+}
+
+impl LoginIps {
+    /// Build from config, `None` when the detection is disabled
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.login_ip_threshold == 0 {
+            return None;
+        }
+
+        Some(Self {
+            threshold: config.login_ip_threshold,
+            window: Duration::from_secs(config.login_ip_window_minutes * 60),
+            accounts: Mutex::new(HashMap::new())
+        })
+    }
+    /// Record a successful login, whether the account is flagged now
+    pub fn record(&self, tenant: &str, user_id: i32, ip: IpAddr) -> bool {
+        self.record_at(tenant, user_id, ip, Instant::now())
+    }
+    /// Code to mail to the owner of a flagged account, `None` while the last one is still valid
+    pub fn issue_code(&self, tenant: &str, user_id: i32) -> Option<String> {
+        self.issue_code_at(tenant, user_id, Instant::now())
+    }
+    /// Check a code from the mail, the account starts over when it matches
+    pub fn verify(&self, tenant: &str, user_id: i32, code: &str) -> bool {
+        self.verify_at(tenant, user_id, code, Instant::now())
+    }
+    fn record_at(&self, tenant: &str, user_id: i32, ip: IpAddr, now: Instant) -> bool {
+        let mut accounts = self.accounts.lock().unwrap();
+        let key = (tenant.to_string(), user_id);
+
+        if accounts.len() >= MAX_TRACKED_ACCOUNTS && !accounts.contains_key(&key) {
+            let window = self.window;
+            accounts.retain(|_, account| account.flagged || account.last_login().is_some_and(|at| now.duration_since(at) < window));
+
+            // Flagged accounts are kept, forgetting them would let the sharing carry on
+            if accounts.len() >= MAX_TRACKED_ACCOUNTS {
+                let oldest = accounts.iter()
+                    .filter(|(_, account)| !account.flagged)
+                    .min_by_key(|(_, account)| account.last_login())
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    accounts.remove(&oldest);
+                }
+            }
+        }
+
+        let account = accounts.entry(key).or_default();
+        let window = self.window;
+        account.ips.retain(|_, at| now.duration_since(*at) < window);
+        account.ips.insert(ip, now);
+
+        if account.ips.len() > self.threshold {
+            account.flagged = true;
+        }
+        account.flagged
+    }
+    fn issue_code_at(&self, tenant: &str, user_id: i32, now: Instant) -> Option<String> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let account = accounts.get_mut(&(tenant.to_string(), user_id)).filter(|account| account.flagged)?;
+
+        if account.code.as_ref().is_some_and(|(_, issued)| now.duration_since(*issued) < CODE_TTL) {
+            return None;
+        }
+
+        let code: String = rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        account.code = Some((code.clone(), now));
+        Some(code)
+    }
+    fn verify_at(&self, tenant: &str, user_id: i32, code: &str, now: Instant) -> bool {
+        let mut accounts = self.accounts.lock().unwrap();
+        let key = (tenant.to_string(), user_id);
+
+        let matches = accounts.get(&key)
+            .and_then(|account| account.code.as_ref())
+            .is_some_and(|(issued_code, issued)| issued_code == code && now.duration_since(*issued) < CODE_TTL);
+
+        if matches {
+            accounts.remove(&key);
+        }
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login_ips(threshold: usize) -> LoginIps {
+        LoginIps::from_config(&Config { login_ip_threshold: threshold, ..Config::for_test() }).unwrap()
+    }
+
+    fn ip(i: u8) -> IpAddr {
+        IpAddr::from([203, 0, 113, i])
+    }
+
+    #[test]
+    fn flagged_past_threshold_distinct_ips() {
+        let login_ips = login_ips(3);
+        let now = Instant::now();
+
+        for i in 0..3 {
+            assert!(!login_ips.record_at("default", 1, ip(i), now));
+        }
+        // The same IPs again don't count twice
+        assert!(!login_ips.record_at("default", 1, ip(0), now));
+        assert!(!login_ips.record_at("default", 2, ip(3), now));
+
+        assert!(login_ips.record_at("default", 1, ip(3), now));
+        assert!(!login_ips.record_at("other", 1, ip(4), now));
+    }
+
+    #[test]
+    fn ips_outside_the_window_are_forgotten() {
+        let login_ips = login_ips(1);
+        let now = Instant::now();
+
+        assert!(!login_ips.record_at("default", 1, ip(0), now));
+        assert!(!login_ips.record_at("default", 1, ip(1), now + login_ips.window));
+        assert!(login_ips.record_at("default", 1, ip(2), now + login_ips.window));
+    }
+
+    #[test]
+    fn flag_stays_until_verified() {
+        let login_ips = login_ips(1);
+        let now = Instant::now();
+
+        assert_eq!(login_ips.issue_code_at("default", 1, now), None);
+        login_ips.record_at("default", 1, ip(0), now);
+        login_ips.record_at("default", 1, ip(1), now);
+
+        // Quiet for longer than the window, still flagged
+        assert!(login_ips.record_at("default", 1, ip(0), now + login_ips.window * 2));
+
+        let code = login_ips.issue_code_at("default", 1, now).unwrap();
+        assert_eq!(login_ips.issue_code_at("default", 1, now), None);
+        assert!(!login_ips.verify_at("default", 1, "wrong", now));
+        assert!(!login_ips.verify_at("default", 2, &code, now));
+        assert!(login_ips.verify_at("default", 1, &code, now));
+
+        assert!(!login_ips.record_at("default", 1, ip(1), now));
+        assert!(!login_ips.verify_at("default", 1, &code, now));
+    }
+
+    #[test]
+    fn codes_expire() {
+        let login_ips = login_ips(1);
+        let now = Instant::now();
+
+        login_ips.record_at("default", 1, ip(0), now);
+        login_ips.record_at("default", 1, ip(1), now);
+        let code = login_ips.issue_code_at("default", 1, now).unwrap();
+
+        assert!(!login_ips.verify_at("default", 1, &code, now + CODE_TTL));
+        let resent = login_ips.issue_code_at("default", 1, now + CODE_TTL).unwrap();
+        assert_ne!(resent, code);
+        assert!(login_ips.verify_at("default", 1, &resent, now + CODE_TTL));
+    }
+}
\ No newline at end of file
@@ -1,2 +1,16 @@
 pub mod auth;
-pub mod user;
\ No newline at end of file
+pub mod captcha;
+pub mod client_ip;
+pub mod cookies;
+pub mod cors;
+pub mod form;
+pub mod headers;
+pub mod https;
+pub mod logging;
+pub mod login_ips;
+pub mod outage;
+pub mod pagination;
+pub mod rate_limit;
+pub mod user;
+pub mod webauthn;
+pub mod tenant;
\ No newline at end of file
@@ -0,0 +1,31 @@
+use actix_web::{dev::{Service, ServiceRequest, ServiceResponse}, http::{header::RETRY_AFTER, HeaderValue, StatusCode}, web::Data, Error};
+use futures::Future;
+
+use crate::{models::MessageResponse, state::State};
+
+/// Middleware function turning internal errors into 503s while the database circuit breaker is open,
+/// so clients know to come back instead of reporting a bug
+pub fn unavailable_during_outage<S, B>(req: ServiceRequest, srv: &mut S) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let state = req.app_data::<Data<State>>().cloned();
+    let response = srv.call(req);
+
+    async move {
+        let response = response.await?;
+        if response.status() != StatusCode::INTERNAL_SERVER_ERROR {
+            return Ok(response);
+        }
+
+        let retry_after = match state.and_then(|state| state.database.retry_after()) {
+            Some(seconds) => seconds,
+            None => return Ok(response)
+        };
+
+        let mut unavailable = MessageResponse::service_unavailable().http_response();
+        unavailable.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+
+        Ok(response.into_response(unavailable.into_body()))
+    }
+}
\ No newline at end of file
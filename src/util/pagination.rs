@@ -0,0 +1,84 @@
+use actix_web::{dev::Payload, web::{Data, Query}, Error, FromRequest, HttpRequest};
+use futures::future::{Ready, err, ok};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::models::MessageResponse;
+use crate::state::State;
+
+#[derive(Deserialize)]
+struct PageQuery {
+    page: Option<i64>,
+    limit: Option<i64>
+}
+
+/// Page of a list endpoint, taken from the `page` (starting at 1) and `limit` query parameters
+pub struct Pagination {
+    pub page: i64,
+    pub limit: i64
+}
+
+impl Pagination {
+    /// Apply the configured default and maximum limit, limits over the cap are clamped
+    fn new(page: Option<i64>, limit: Option<i64>, config: &Config) -> Result<Self, MessageResponse> {
+        let page = page.unwrap_or(1);
+        let limit = limit.unwrap_or(config.page_limit_default);
+
+        if page < 1 || limit < 1 {
+            return Err(MessageResponse::bad_request().with_message("Page and limit have to be at least 1").with_error_code("INVALID_PAGINATION"));
+        }
+
+        Ok(Pagination { page, limit: limit.min(config.page_limit_max) })
+    }
+    /// Rows to skip before this page
+    pub fn offset(&self) -> i64 {
+        (self.page - 1).saturating_mul(self.limit)
+    }
+}
+
+impl FromRequest for Pagination {
+    type Error = Error;
+    type Future = Ready<Result<Pagination, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let state = req.app_data::<Data<State>>().expect("State was not found");
+
+        let query = match Query::<PageQuery>::from_query(req.query_string()) {
+            Ok(query) => query,
+            Err(_) => return err(Error::from(MessageResponse::bad_request().with_message("Page and limit have to be numbers").with_error_code("INVALID_PAGINATION")))
+        };
+
+        match Pagination::new(query.page, query.limit, &state.config) {
+            Ok(pagination) => ok(pagination),
+            Err(response) => err(Error::from(response))
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_when_absent() {
+        let pagination = Pagination::new(None, None, &Config::for_test()).ok().unwrap();
+
+        assert_eq!((pagination.page, pagination.limit), (1, 25));
+        assert_eq!(pagination.offset(), 0);
+    }
+
+    #[test]
+    fn limit_over_the_cap_is_clamped() {
+        let pagination = Pagination::new(Some(3), Some(1000), &Config::for_test()).ok().unwrap();
+
+        assert_eq!((pagination.page, pagination.limit), (3, 100));
+        assert_eq!(pagination.offset(), 200);
+    }
+
+    #[test]
+    fn page_below_one_is_rejected() {
+        assert!(Pagination::new(Some(-1), None, &Config::for_test()).is_err());
+        assert!(Pagination::new(Some(0), None, &Config::for_test()).is_err());
+        assert!(Pagination::new(None, Some(0), &Config::for_test()).is_err());
+    }
+}
\ No newline at end of file
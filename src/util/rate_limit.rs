@@ -0,0 +1,165 @@
+use actix_web::{dev::{Service, ServiceRequest, ServiceResponse}, http::{header::RETRY_AFTER, HeaderValue}, web::Data, Error, HttpResponse};
+use futures::future::{Either, Ready, ok};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::{config::Config, models::MessageResponse, state::State, util::client_ip::client_ip};
+
+/// Buckets kept before the full ones are dropped, a full bucket is the same as no bucket
+const MAX_TRACKED_IPS: usize = 10_000;
+
+/// Decides whether a client may send another request, so the buckets can live somewhere shared
+/// like Redis once there's more than one instance
+pub trait RateLimiter: Send + Sync {
+    /// Take a token for a request, or get the seconds until the next one is available
+    fn check(&self, ip: IpAddr) -> Result<(), u64>;
+}
+
+/// Build the configured limiter, `None` when rate limiting is disabled
+pub fn from_config(config: &Config) -> Option<Box<dyn RateLimiter>> {
+    if config.rate_limit_per_minute == 0 {
+        return None;
+    }
+
+    Some(Box::new(MemoryRateLimiter::new(config.rate_limit_per_minute, config.rate_limit_burst)))
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant
+}
+
+struct Buckets {
+    by_ip: HashMap<IpAddr, Bucket>,
+    /// Size at which full buckets are dropped, doubling what survived the last pass
+    /// so pruning stays rare while most buckets are in use
+    prune_at: usize
+}
+
+/// Token bucket per client IP in this process, refilling at a steady rate up to the burst size
+pub struct MemoryRateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<Buckets>
+}
+
+impl MemoryRateLimiter {
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            per_second: per_minute as f64 / 60.0,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(Buckets { by_ip: HashMap::new(), prune_at: MAX_TRACKED_IPS })
+        }
+    }
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.by_ip.len() >= buckets.prune_at {
+            let (per_second, burst) = (self.per_second, self.burst);
+            buckets.by_ip.retain(|_, bucket| bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second < burst);
+            buckets.prune_at = (buckets.by_ip.len() * 2).max(MAX_TRACKED_IPS);
+        }
+
+        let bucket = buckets.by_ip.entry(ip).or_insert(Bucket { tokens: self.burst, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.per_second).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.per_second).ceil() as u64)
+        }
+    }
+}
+
+impl RateLimiter for MemoryRateLimiter {
+    fn check(&self, ip: IpAddr) -> Result<(), u64> {
+        self.check_at(ip, Instant::now())
+    }
+}
+
+/// Middleware function rejecting clients that send requests faster than the configured rate
+pub fn limit_requests<S, B>(req: ServiceRequest, srv: &mut S) -> Either<S::Future, Ready<Result<ServiceResponse<B>, Error>>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let retry_after = match req.app_data::<Data<State>>() {
+        Some(state) => match (&state.rate_limiter, client_ip(req.head(), &state.config)) {
+            (Some(limiter), Some(ip)) => limiter.check(ip).err(),
+            _ => None
+        },
+        None => None
+    };
+
+    match retry_after {
+        Some(seconds) => Either::Right(ok(req.into_response(too_many_requests(seconds).into_body()))),
+        None => Either::Left(srv.call(req))
+    }
+}
+
+fn too_many_requests(retry_after: u64) -> HttpResponse {
+    let mut response = MessageResponse::too_many_requests().http_response();
+    response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use std::time::Duration;
+
+    #[test]
+    fn burst_over_the_limit_is_rejected() {
+        let limiter = MemoryRateLimiter::new(60, 2);
+        let ip = "203.0.113.9".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at(ip, now), Ok(()));
+        assert_eq!(limiter.check_at(ip, now), Ok(()));
+        assert_eq!(limiter.check_at(ip, now), Err(1));
+
+        let response = too_many_requests(1);
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "1");
+    }
+
+    #[test]
+    fn other_ips_are_unaffected() {
+        let limiter = MemoryRateLimiter::new(60, 1);
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("203.0.113.9".parse().unwrap(), now), Ok(()));
+        assert!(limiter.check_at("203.0.113.9".parse().unwrap(), now).is_err());
+        assert_eq!(limiter.check_at("198.51.100.1".parse().unwrap(), now), Ok(()));
+    }
+
+    #[test]
+    fn bucket_refills() {
+        let limiter = MemoryRateLimiter::new(60, 1);
+        let ip = "203.0.113.9".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at(ip, now), Ok(()));
+        assert!(limiter.check_at(ip, now + Duration::from_millis(500)).is_err());
+        assert_eq!(limiter.check_at(ip, now + Duration::from_secs(1)), Ok(()));
+    }
+
+    #[test]
+    fn busy_buckets_are_not_scanned_on_every_request() {
+        let limiter = MemoryRateLimiter::new(1, 1);
+        let now = Instant::now();
+
+        // Every bucket is empty, so pruning can't drop any of them
+        for i in 0..=MAX_TRACKED_IPS as u32 {
+            let _ = limiter.check_at(IpAddr::from(i.to_be_bytes()), now);
+        }
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_ip.len(), MAX_TRACKED_IPS + 1);
+        assert_eq!(buckets.prune_at, MAX_TRACKED_IPS * 2);
+    }
+}
\ No newline at end of file
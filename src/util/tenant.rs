@@ -0,0 +1,76 @@
+use actix_web::{dev::Payload, http::{header::HOST, uri::Authority}, web::Data, Error, FromRequest, HttpRequest};
+use futures::future::{Ready, err, ok};
+
+use crate::config::Config;
+use crate::models::MessageResponse;
+use crate::state::State;
+
+/// Tenant everything belongs to when no host map is configured
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Tenant (logical instance) a request was made to
+pub struct Tenant(pub String);
+
+/// Resolve the tenant of a request from its Host header
+pub fn resolve_tenant(req: &HttpRequest, config: &Config) -> Option<String> {
+    if config.tenant_hosts.is_empty() {
+        return Some(DEFAULT_TENANT.to_string());
+    }
+
+    let host = req.headers().get(HOST)?.to_str().ok()?;
+    // Drop the port if there is one
+    let authority: Authority = host.parse().ok()?;
+
+    config.tenant_hosts.get(&authority.host().to_lowercase()).cloned()
+}
+
+/// Error for a host that doesn't belong to any tenant
+pub fn unknown_tenant() -> MessageResponse {
+    MessageResponse::not_found().with_message("This instance does not exist").with_error_code("UNKNOWN_TENANT")
+}
+
+impl FromRequest for Tenant {
+    type Error = Error;
+    type Future = Ready<Result<Tenant, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let state = req.app_data::<Data<State>>().expect("State was not found");
+
+        match resolve_tenant(req, &state.config) {
+            Some(tenant) => ok(Tenant(tenant)),
+            None => err(Error::from(unknown_tenant()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn hosts() -> Config {
+        Config {
+            tenant_hosts: vec![("a.example.com".to_string(), "a".to_string()), ("b.example.com".to_string(), "b".to_string())].into_iter().collect(),
+            ..Config::for_test()
+        }
+    }
+
+    #[test]
+    fn tenant_comes_from_the_host() {
+        let config = hosts();
+        let tenant = |host: &str| resolve_tenant(&TestRequest::default().header(HOST, host).to_http_request(), &config);
+
+        assert_eq!(tenant("a.example.com").as_deref(), Some("a"));
+        assert_eq!(tenant("B.example.com:8080").as_deref(), Some("b"));
+        assert_eq!(tenant("c.example.com"), None);
+        assert_eq!(resolve_tenant(&TestRequest::default().to_http_request(), &config), None);
+    }
+
+    #[test]
+    fn without_host_map_everything_is_the_default_tenant() {
+        let req = TestRequest::default().header(HOST, "a.example.com").to_http_request();
+
+        assert_eq!(resolve_tenant(&req, &Config::for_test()).as_deref(), Some(DEFAULT_TENANT));
+    }
+}
\ No newline at end of file
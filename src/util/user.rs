@@ -1,15 +1,17 @@
-use actix_web::http::StatusCode;
 use rand::Rng;
 
 use crate::models::MessageResponse;
 
+/// Argon2 hash with the same parameters as `new_password`, verified against when an account doesn't exist
+const DECOY_HASH: &str = "$argon2i$v=19$m=4096,t=3,p=1$ZGVjb3lzYWx0ZGVjb3lzYWx0ZGVjb3lzYWx0ZGVjb3lzYWx0$BpT5EE+nRwaZIDdvV2x/U2IfGEFdwiyjzQNCkWfR08M";
+
 /// Checks and generates a new hashed password
 pub fn new_password(password: &str) -> Result<String, MessageResponse> {
     let password_length = password.len();
     if password_length < 6 {
-        return Err(MessageResponse::new(StatusCode::BAD_REQUEST, "Password too short (minimum 6 characters)"));
+        return Err(MessageResponse::bad_request().with_message("Password too short (minimum 6 characters)"));
     } else if password_length > 128 {
-        return Err(MessageResponse::new(StatusCode::BAD_REQUEST, "Password too long (maximum 128 characters)"));
+        return Err(MessageResponse::bad_request().with_message("Password too long (maximum 128 characters)"));
     }
 
     // Generate a random salt
@@ -28,4 +30,110 @@ pub fn new_password(password: &str) -> Result<String, MessageResponse> {
     };
 
     Ok(hash)
+}
+
+/// Whether a username is reserved, ignoring case
+pub fn is_reserved_username(username: &str, reserved: &[String]) -> bool {
+    reserved.contains(&username.to_lowercase())
+}
+
+/// Check that a stored password hash is a format we can verify.
+/// Imported accounts can carry argon2 or bcrypt hashes
+pub fn is_supported_hash(hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        // $argon2id$v=19$m=4096,t=3,p=1$<salt>$<hash>
+        let parts: Vec<&str> = hash.split('$').collect();
+        return parts.len() == 6 && parts[1..].iter().all(|part| !part.is_empty());
+    }
+
+    if is_bcrypt_hash(hash) {
+        // $2b$<cost>$<22 salt chars><31 hash chars>
+        let parts: Vec<&str> = hash.split('$').collect();
+        return hash.len() == 60 && parts.len() == 4 && parts[2].parse::<u32>().is_ok_and(|cost| (4..=31).contains(&cost));
+    }
+
+    false
+}
+
+/// Verify a password against a stored argon2 or bcrypt hash
+pub fn verify_password(hash: &str, password: &str) -> Result<bool, MessageResponse> {
+    let matches = if is_bcrypt_hash(hash) {
+        bcrypt::verify(password, hash).ok()
+    } else {
+        argon2::verify_encoded(hash, password.as_bytes()).ok()
+    };
+
+    matches.ok_or_else(MessageResponse::internal_server_error)
+}
+
+/// Spend as long as a real password check would, so failed logins take
+/// the same time whether or not the account exists
+pub fn verify_decoy(password: &str) {
+    let _ = verify_password(DECOY_HASH, password);
+}
+
+fn is_bcrypt_hash(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BCRYPT_HASH: &str = "$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW";
+
+    #[test]
+    fn reserved_usernames_ignore_case() {
+        let reserved = vec!["admin".to_string(), "root".to_string()];
+
+        assert!(is_reserved_username("Admin", &reserved));
+        assert!(is_reserved_username("ROOT", &reserved));
+        assert!(!is_reserved_username("administrator", &reserved));
+        assert!(!is_reserved_username("kawaii", &reserved));
+    }
+
+    #[test]
+    fn supported_hashes() {
+        assert!(is_supported_hash(DECOY_HASH));
+        assert!(is_supported_hash(BCRYPT_HASH));
+        assert!(is_supported_hash(&BCRYPT_HASH.replacen("$2b$", "$2y$", 1)));
+        assert!(is_supported_hash(&BCRYPT_HASH.replacen("$12$", "$04$", 1)));
+    }
+
+    #[test]
+    fn unsupported_hashes() {
+        // Argon2 with a missing or empty part
+        assert!(!is_supported_hash("$argon2id$v=19$m=4096,t=3,p=1$c2FsdHNhbHQ"));
+        assert!(!is_supported_hash("$argon2id$v=19$m=4096,t=3,p=1$$aGFzaA"));
+
+        // Bcrypt with a cost out of range, a cost that isn't a number, or the wrong length
+        assert!(!is_supported_hash(&BCRYPT_HASH.replacen("$12$", "$03$", 1)));
+        assert!(!is_supported_hash(&BCRYPT_HASH.replacen("$12$", "$32$", 1)));
+        assert!(!is_supported_hash(&BCRYPT_HASH.replacen("$12$", "$xx$", 1)));
+        assert!(!is_supported_hash(&BCRYPT_HASH[..59]));
+        assert!(!is_supported_hash(&format!("{}a", BCRYPT_HASH)));
+        assert!(!is_supported_hash("$2b$12$R9h/cIPz0gi.URNNX3kh2O$ST9/PgBkqquzi.Ss7KIUgO2t0jWMUW"));
+
+        assert!(!is_supported_hash("plaintext"));
+        assert!(!is_supported_hash("$1$md5crypt$hash"));
+        assert!(!is_supported_hash(""));
+    }
+
+    #[test]
+    fn bcrypt_hashes_verify() {
+        let hash = bcrypt::hash("hunter22", 4).unwrap();
+
+        assert!(is_supported_hash(&hash));
+        assert!(verify_password(&hash, "hunter22").ok().unwrap());
+        assert!(!verify_password(&hash, "hunter23").ok().unwrap());
+    }
+
+    #[test]
+    fn decoy_hash_costs_as_much_as_a_real_one() {
+        assert!(!verify_password(DECOY_HASH, "x").ok().unwrap());
+
+        // Variant, version and m/t/p parameters, the salt and hash follow
+        let real = new_password("hunter22").ok().unwrap();
+        let params = |hash: &str| hash.split('$').take(4).collect::<Vec<_>>().join("$");
+        assert_eq!(params(DECOY_HASH), params(&real));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,197 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use webauthn_rs::{AuthenticationState, RegistrationState, Webauthn};
+use webauthn_rs::ephemeral::WebauthnEphemeralConfig;
+use webauthn_rs::proto::{CreationChallengeResponse, Credential, CredentialID, PublicKeyCredential, RegisterPublicKeyCredential, RequestChallengeResponse};
+
+use crate::config::Config;
+
+/// How long a started registration or login can be finished for
+const CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Login waiting for its challenge to be signed
+struct PendingLogin {
+    state: AuthenticationState,
+    user_id: i32,
+    tenant: String,
+    started: Instant
+}
+
+/// Passkey login that was verified
+pub struct PasskeyLogin {
+    pub user_id: i32,
+    pub tenant: String,
+    pub cred_id: CredentialID,
+    pub counter: u32
+}
+
+/// Passkey registration and login, with challenges kept in memory until they're answered or expire
+pub struct Passkeys {
+    webauthn: Webauthn<WebauthnEphemeralConfig>,
+    registrations: Mutex<HashMap<i32, (RegistrationState, Instant)>>,
+    logins: Mutex<HashMap<String, PendingLogin>>
+}
+
+impl Passkeys {
+    /// Build from config, `None` when passkeys are disabled
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let origin = config.webauthn_origin.as_ref()?;
+
+        Some(Self {
+            webauthn: Webauthn::new(WebauthnEphemeralConfig::new(&config.webauthn_rp_name, origin, &config.webauthn_rp_id, None)),
+            registrations: Mutex::new(HashMap::new()),
+            logins: Mutex::new(HashMap::new())
+        })
+    }
+    /// Start registering a passkey, replacing a registration the user didn't finish
+    pub fn begin_registration(&self, user_id: i32, username: &str) -> Option<CreationChallengeResponse> {
+        self.begin_registration_at(user_id, username, Instant::now())
+    }
+    /// Verify the new credential against the user's pending registration
+    pub fn finish_registration(&self, user_id: i32, response: &RegisterPublicKeyCredential) -> Option<Credential> {
+        let state = self.take_registration(user_id, Instant::now())?;
+
+        // Reused credential ids are caught by the unique index when storing
+        let (credential, _) = self.webauthn.register_credential(response, &state, |_| Ok(false)).ok()?;
+        Some(credential)
+    }
+    /// Start a login with one of the user's passkeys
+    pub fn begin_login(&self, user_id: i32, tenant: &str, credentials: Vec<Credential>) -> Option<(String, RequestChallengeResponse)> {
+        self.begin_login_at(user_id, tenant, credentials, Instant::now())
+    }
+    /// Verify a signed challenge, each login can only be tried once
+    pub fn finish_login(&self, login_id: &str, response: &PublicKeyCredential) -> Option<PasskeyLogin> {
+        let login = self.take_login(login_id, Instant::now())?;
+
+        let (cred_id, data) = self.webauthn.authenticate_credential(response, &login.state).ok()?;
+
+        Some(PasskeyLogin {
+            user_id: login.user_id,
+            tenant: login.tenant,
+            cred_id: cred_id.clone(),
+            counter: data.counter
+        })
+    }
+    fn begin_registration_at(&self, user_id: i32, username: &str, now: Instant) -> Option<CreationChallengeResponse> {
+        let (challenge, state) = self.webauthn.generate_challenge_register(username, false).ok()?;
+
+        let mut registrations = self.registrations.lock().unwrap();
+        registrations.retain(|_, (_, started)| now.duration_since(*started) < CHALLENGE_TTL);
+        registrations.insert(user_id, (state, now));
+
+        Some(challenge)
+    }
+    /// Pending registration of the user, removed so it can only be answered once
+    fn take_registration(&self, user_id: i32, now: Instant) -> Option<RegistrationState> {
+        let (state, started) = self.registrations.lock().unwrap().remove(&user_id)?;
+        if now.duration_since(started) >= CHALLENGE_TTL {
+            return None;
+        }
+
+        Some(state)
+    }
+    fn begin_login_at(&self, user_id: i32, tenant: &str, credentials: Vec<Credential>, now: Instant) -> Option<(String, RequestChallengeResponse)> {
+        let (challenge, state) = self.webauthn.generate_challenge_authenticate(credentials).ok()?;
+
+        let login_id: String = rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+
+        let mut logins = self.logins.lock().unwrap();
+        logins.retain(|_, login| now.duration_since(login.started) < CHALLENGE_TTL);
+        logins.insert(login_id.clone(), PendingLogin {
+            state,
+            user_id,
+            tenant: tenant.to_string(),
+            started: now
+        });
+
+        Some((login_id, challenge))
+    }
+    /// Pending login, removed so a failed attempt can't be retried against the same challenge
+    fn take_login(&self, login_id: &str, now: Instant) -> Option<PendingLogin> {
+        let login = self.logins.lock().unwrap().remove(login_id)?;
+        if now.duration_since(login.started) >= CHALLENGE_TTL {
+            return None;
+        }
+
+        Some(login)
+    }
+}
+
+/// Key a credential is stored under
+pub fn credential_key(cred_id: &[u8]) -> String {
+    base64::encode_config(cred_id, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn passkeys() -> Passkeys {
+        Passkeys::from_config(&Config {
+            webauthn_origin: Some("https://kawaii.example.com".into()),
+            webauthn_rp_id: "kawaii.example.com".into(),
+            ..Config::for_test()
+        }).unwrap()
+    }
+
+    #[test]
+    fn disabled_without_an_origin() {
+        assert!(Passkeys::from_config(&Config::for_test()).is_none());
+    }
+
+    #[test]
+    fn registrations_can_only_be_finished_once() {
+        let passkeys = passkeys();
+        let now = Instant::now();
+
+        passkeys.begin_registration_at(1, "kawaii", now).unwrap();
+        assert!(passkeys.take_registration(2, now).is_none());
+        assert!(passkeys.take_registration(1, now).is_some());
+        assert!(passkeys.take_registration(1, now).is_none());
+    }
+
+    #[test]
+    fn registrations_expire() {
+        let passkeys = passkeys();
+        let now = Instant::now();
+
+        passkeys.begin_registration_at(1, "kawaii", now).unwrap();
+        assert!(passkeys.take_registration(1, now + CHALLENGE_TTL).is_none());
+
+        // Expired registrations of other users are dropped when a new one starts
+        passkeys.begin_registration_at(1, "kawaii", now).unwrap();
+        passkeys.begin_registration_at(2, "other", now + CHALLENGE_TTL).unwrap();
+        assert_eq!(passkeys.registrations.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn logins_can_only_be_finished_once() {
+        let passkeys = passkeys();
+        let now = Instant::now();
+
+        let (login_id, _) = passkeys.begin_login_at(1, "main", Vec::new(), now).unwrap();
+        assert!(passkeys.take_login("unknown", now).is_none());
+
+        let login = passkeys.take_login(&login_id, now).unwrap();
+        assert_eq!((login.user_id, login.tenant.as_str()), (1, "main"));
+        assert!(passkeys.take_login(&login_id, now).is_none());
+    }
+
+    #[test]
+    fn logins_expire() {
+        let passkeys = passkeys();
+        let now = Instant::now();
+
+        let (login_id, _) = passkeys.begin_login_at(1, "main", Vec::new(), now).unwrap();
+        assert!(passkeys.take_login(&login_id, now + CHALLENGE_TTL - Duration::from_secs(1)).is_some());
+
+        let (login_id, _) = passkeys.begin_login_at(1, "main", Vec::new(), now).unwrap();
+        assert!(passkeys.take_login(&login_id, now + CHALLENGE_TTL).is_none());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,128 @@
+use actix_web::{client::Client, rt};
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::Duration;
+
+/// Header carrying the hex HMAC-SHA256 of the request body
+const SIGNATURE_HEADER: &str = "X-Kawaii-Signature";
+
+/// Delivery attempts per webhook before giving up
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Body POSTed to every webhook
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    event: &'a str,
+    timestamp: i64,
+    data: serde_json::Value
+}
+
+/// Sends signed event notifications to the configured webhooks
+pub struct Webhooks {
+    urls: Vec<String>,
+    secret: String
+}
+
+impl Webhooks {
+    pub fn new(urls: &[String], secret: &str) -> Self {
+        Self {
+            urls: urls.to_vec(),
+            secret: secret.into()
+        }
+    }
+    /// Notify all webhooks of an event. Deliveries run in the background
+    /// and are retried with exponential backoff, so callers never wait on them
+    pub fn dispatch(&self, event: &str, data: serde_json::Value) {
+        if self.urls.is_empty() {
+            return;
+        }
+
+        let body = match serde_json::to_vec(&WebhookPayload { event, timestamp: Utc::now().timestamp(), data }) {
+            Ok(body) => body,
+            Err(_) => return
+        };
+
+        let signature = sign(&self.secret, &body);
+
+        for url in &self.urls {
+            rt::spawn(deliver(url.clone(), body.clone(), signature.clone()));
+        }
+    }
+}
+
+/// POST a payload to a single webhook until it answers with a success status
+async fn deliver(url: String, body: Vec<u8>, signature: String) {
+    let client = Client::default();
+
+    for attempt in 0..MAX_ATTEMPTS {
+        if attempt > 0 {
+            rt::time::delay_for(Duration::from_secs(1 << attempt)).await;
+        }
+
+        let response = client.post(&url)
+            .header("Content-Type", "application/json")
+            .header(SIGNATURE_HEADER, signature.as_str())
+            .send_body(body.clone())
+            .await;
+
+        if let Ok(response) = response {
+            if response.status().is_success() {
+                return;
+            }
+        }
+    }
+
+    tracing::warn!(url = %url, attempts = MAX_ATTEMPTS, "webhook delivery failed, giving up");
+}
+
+/// Hex encoded HMAC-SHA256 of the body with the webhook secret
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn signature_is_hex_hmac_sha256() {
+        // Known answer from the HMAC example on Wikipedia
+        assert_eq!(sign("key", b"The quick brown fox jumps over the lazy dog"), "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+    }
+
+    #[actix_rt::test]
+    async fn delivery_carries_the_signature_of_the_body() {
+        let received: Arc<Mutex<Vec<(String, web::Bytes)>>> = Arc::default();
+
+        let server = {
+            let received = received.clone();
+            test::start(move || {
+                let received = received.clone();
+                App::new().route("/hook", web::post().to(move |req: HttpRequest, body: web::Bytes| {
+                    let signature = req.headers().get(SIGNATURE_HEADER).map(|value| value.to_str().unwrap().to_string()).unwrap_or_default();
+                    received.lock().unwrap().push((signature, body));
+                    HttpResponse::Ok()
+                }))
+            })
+        };
+
+        let body = br#"{"event":"user.registered"}"#.to_vec();
+        deliver(server.url("/hook"), body.clone(), sign("webhook secret", &body)).await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0, sign("webhook secret", &body));
+        assert_eq!(received[0].1, body);
+    }
+}
\ No newline at end of file